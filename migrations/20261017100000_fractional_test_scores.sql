@@ -0,0 +1,15 @@
+CREATE TABLE tests_new (
+    id          INTEGER PRIMARY KEY NOT NULL,
+    subtask_id  INTEGER NOT NULL,
+    test        INTEGER NOT NULL,
+    memory      INTEGER,
+    time        INTEGER,
+    verdict     TEXT NOT NULL,
+    score       REAL NOT NULL,
+    message     TEXT,
+    FOREIGN KEY (subtask_id) REFERENCES subtasks(id) ON DELETE CASCADE
+);
+
+INSERT INTO tests_new SELECT id, subtask_id, test, memory, time, verdict, score, message FROM tests;
+DROP TABLE tests;
+ALTER TABLE tests_new RENAME TO tests;
@@ -0,0 +1,2 @@
+ALTER TABLE tests ADD COLUMN exit_code INTEGER;
+ALTER TABLE tests ADD COLUMN signal INTEGER;
@@ -0,0 +1,10 @@
+-- Output files of output-only submissions, kept until they are judged
+CREATE TABLE IF NOT EXISTS outputs (
+    id             INTEGER PRIMARY KEY NOT NULL,
+    submission_id  INTEGER NOT NULL,
+    filename       TEXT NOT NULL,
+    contents       BLOB NOT NULL,
+    FOREIGN KEY (submission_id) REFERENCES submissions(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS submissions_verdict ON submissions(verdict);
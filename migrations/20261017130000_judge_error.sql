@@ -0,0 +1,2 @@
+-- Why the judge failed to judge a submission, shown to admins
+ALTER TABLE submissions ADD COLUMN judge_error TEXT;
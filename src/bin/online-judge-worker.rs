@@ -0,0 +1,58 @@
+use std::env;
+
+use color_eyre::Result;
+use online_judge::{judge::remote::Token, worker};
+use pico_args::Arguments;
+use tracing_subscriber::{prelude::*, EnvFilter};
+use tracing_tree::HierarchicalLayer;
+
+const HELP: &str = "\
+Online Judge Worker
+
+USAGE:
+  online-judge-worker [OPTIONS]
+
+FLAGS:
+  -h, --help          Display help information
+
+OPTIONS:
+  -s, --server        Set address the web server listens for workers on (127.0.0.1:8100)
+  -c, --config        Set judge config path (judge.toml)
+  -t, --token         Set shared secret expected by the web server
+";
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    color_eyre::install()?;
+    dotenvy::dotenv().ok();
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(HierarchicalLayer::new(2))
+        .try_init()?;
+
+    let mut args = Arguments::from_env();
+
+    if args.contains(["-h", "--help"]) {
+        println!("{HELP}");
+    } else {
+        let config = worker::Config {
+            server_address: args
+                .opt_value_from_str(["-s", "--server"])?
+                .or_else(|| env::var("WORKER_SERVER_ADDRESS").ok())
+                .unwrap_or_else(|| String::from("127.0.0.1:8100")),
+            judge_config_path: args
+                .opt_value_from_str(["-c", "--config"])?
+                .unwrap_or_else(|| String::from("judge.toml"))
+                .into(),
+            token: args
+                .opt_value_from_str(["-t", "--token"])?
+                .or_else(|| env::var("WORKER_TOKEN").ok().map(Token::from)),
+        };
+
+        tracing::info!("starting worker with config: {config:#?}");
+        worker::run(config).await?;
+    }
+
+    Ok(())
+}
@@ -1,15 +1,17 @@
-use std::{fmt, path::PathBuf, str::FromStr};
+use std::{collections::HashMap, fmt, path::PathBuf, str::FromStr, sync::Arc};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_with::DeserializeFromStr;
 use thiserror::Error;
 use time::Duration;
 
-use crate::judge::ResourceLimits;
+use crate::judge::{
+    Checker, Comparison, Interactor, JudgeError, JudgeResult, Language, ResourceLimits, Scoring,
+};
 
 mod loader;
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub struct Contest {
     pub name: String,
     pub path: PathBuf,
@@ -21,15 +23,74 @@ pub struct Contest {
     pub rlimits: ContestResourceLimits,
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub struct Task {
     pub name: String,
+    /// The directory the task was loaded from, whose test data runs may not read. Remote workers
+    /// only have the tests themselves.
+    pub path: Option<PathBuf>,
     pub page: String,
     pub examples: Vec<Example>,
     pub subtasks: Vec<Subtask>,
     pub constraints: Vec<String>,
     pub tests: Vec<Test>,
     pub difficulty: Option<Difficulty>,
+    pub kind: TaskKind,
+    /// Skip the remaining tests of failed subtasks
+    pub short_circuit: bool,
+    pub comparison: Comparison,
+    pub checker: Option<Arc<Checker>>,
+    pub interactor: Option<Arc<Interactor>>,
+    /// Graders by language name, for tasks where contestants implement a function
+    pub graders: HashMap<String, Arc<Grader>>,
+}
+
+impl Task {
+    /// The grader to compile a submission with, if the task has graders. Languages which cannot
+    /// compile the grader apart from submissions are unsupported, as submissions could read it.
+    pub fn grader(&self, language: &Language) -> JudgeResult<Option<&Grader>> {
+        if self.graders.is_empty() {
+            return Ok(None);
+        }
+
+        self.graders
+            .get(&language.name)
+            .filter(|_| language.build.is_some() && language.grader.is_some())
+            .map(|grader| Some(grader.as_ref()))
+            .ok_or_else(|| JudgeError::UnsupportedLanguage(language.name.clone()))
+    }
+
+    pub fn supports(&self, language: &Language) -> bool {
+        self.grader(language).is_ok()
+    }
+}
+
+/// Task-supplied files that are compiled together with a submission and call into it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Grader {
+    /// Compiled on their own with the language's `grader` command
+    pub sources: Vec<String>,
+    /// Sources and headers. Only the headers are copied next to the submission.
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+impl Grader {
+    /// The files which are not sources, which submissions may include.
+    pub fn headers(&self) -> impl Iterator<Item = &(String, Vec<u8>)> {
+        self.files
+            .iter()
+            .filter(|(path, _)| !self.sources.contains(path))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TaskKind {
+    #[default]
+    Batch,
+    Interactive,
+    /// Contestants are given the inputs and upload their outputs, nothing is run
+    OutputOnly,
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize)]
@@ -40,20 +101,31 @@ pub struct Example {
     pub comment: Option<String>,
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize)]
-#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Subtask {
-    pub tests: usize,
-    #[serde(default)]
+    /// Indices into the tests of the task, including the tests of included subtasks
+    pub tests: Vec<usize>,
+    pub includes: Vec<usize>,
+    pub points: u32,
+    pub scoring: Scoring,
     pub constraints: Vec<String>,
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Test {
-    pub subtask: usize,
+    pub name: String,
     pub input: String,
     pub output: String,
+    /// Other accepted reference outputs, loaded from `N.out.alt1`, `N.out.alt2`, ...
+    #[serde(default)]
+    pub alternatives: Vec<String>,
+}
+
+impl Test {
+    pub fn outputs(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.output.as_str()).chain(self.alternatives.iter().map(String::as_str))
+    }
 }
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, DeserializeFromStr)]
@@ -91,7 +163,7 @@ impl FromStr for Difficulty {
     }
 }
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct ContestResourceLimits {
     pub build: ResourceLimits,
@@ -1,9 +1,14 @@
-use std::{fs, io, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::Path,
+};
 
 use pulldown_cmark::{BrokenLink, Options, Parser};
 use thiserror::Error;
 
 use super::*;
+use crate::judge::{Config as JudgeConfig, JudgeError, JudgeResult, Language};
 
 #[derive(Debug, Error)]
 pub enum LoadContestError {
@@ -15,6 +20,14 @@ pub enum LoadContestError {
     NoFrontmatter,
     #[error("no subtasks in task")]
     NoSubtasks,
+    #[error("invalid task: {0}")]
+    InvalidTask(&'static str),
+    #[error("missing input or output file for test {0}")]
+    MissingTest(String),
+    #[error("no tests match {0}")]
+    NoMatchingTests(String),
+    #[error("failed to prepare judge program: {0}")]
+    Program(JudgeError),
 }
 
 #[derive(Debug, Deserialize)]
@@ -32,8 +45,11 @@ struct ContestFrontmatter {
 }
 
 impl Contest {
-    #[tracing::instrument(skip(path))]
-    pub fn load(path: impl AsRef<Path>) -> Result<Self, LoadContestError> {
+    #[tracing::instrument(skip(path, judge_config))]
+    pub fn load(
+        path: impl AsRef<Path>,
+        judge_config: &JudgeConfig,
+    ) -> Result<Self, LoadContestError> {
         let path = path.as_ref();
 
         tracing::debug!("loading contest at path {}", path.display());
@@ -46,13 +62,12 @@ impl Contest {
         for task_path in frontmatter.task_paths {
             let path = path.join(task_path);
             if !path.is_dir() {
-                return Err(LoadContestError::Io(io::Error::new(
-                    io::ErrorKind::Other, // NotADirectory
-                    "task is not a directory",
+                return Err(LoadContestError::Io(io::Error::other(
+                    "task is not a directory", // NotADirectory
                 )));
             }
 
-            tasks.push(Task::load(&path)?);
+            tasks.push(Task::load(&path, judge_config, frontmatter.rlimits)?);
         }
 
         Ok(Contest {
@@ -74,15 +89,87 @@ struct TaskFrontmatter {
     name: String,
     #[serde(default)]
     examples: Vec<Example>,
-    subtasks: Vec<Subtask>,
+    subtasks: Vec<SubtaskFrontmatter>,
     #[serde(default)]
     constraints: Vec<String>,
     #[serde(default)]
     difficulty: Option<Difficulty>,
+    #[serde(default, rename = "type")]
+    kind: TaskKind,
+    #[serde(default)]
+    short_circuit: bool,
+    #[serde(default)]
+    comparison: Comparison,
+    #[serde(default)]
+    checker: Option<ProgramFrontmatter>,
+    #[serde(default)]
+    interactor: Option<ProgramFrontmatter>,
+    #[serde(default)]
+    graders: HashMap<String, GraderFrontmatter>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SubtaskFrontmatter {
+    #[serde(default)]
+    tests: TestSelection,
+    #[serde(default)]
+    includes: Vec<usize>,
+    #[serde(default)]
+    points: Option<u32>,
+    #[serde(default)]
+    scoring: Scoring,
+    #[serde(default)]
+    constraints: Vec<String>,
+}
+
+/// Tests are either numbered sequentially across subtasks (`tests: 5`), or listed by name with
+/// `*` and `?` wildcards (`tests: [1, 2, large-*]`).
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TestSelection {
+    Count(usize),
+    List(Vec<TestPattern>),
+}
+
+impl Default for TestSelection {
+    fn default() -> Self {
+        TestSelection::List(Vec::new())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TestPattern {
+    Number(usize),
+    Name(String),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProgramFrontmatter {
+    source: String,
+    language: String,
+    #[serde(default)]
+    files: Vec<String>,
+    #[serde(default)]
+    rlimits: Option<ResourceLimits>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GraderFrontmatter {
+    sources: Vec<String>,
+    #[serde(default)]
+    files: Vec<String>,
 }
 
 impl Task {
-    fn load(path: &Path) -> Result<Self, LoadContestError> {
+    fn load(
+        path: &Path,
+        judge_config: &JudgeConfig,
+        rlimits: ContestResourceLimits,
+    ) -> Result<Self, LoadContestError> {
         tracing::trace!("loading task at path {}", path.display());
         let input = fs::read_to_string(path.join("task.md"))?;
 
@@ -93,37 +180,328 @@ impl Task {
             return Err(LoadContestError::NoSubtasks);
         }
 
-        let mut tests = Vec::new();
         let test_dir = path.join("tests");
+        let test_names = test_names(&test_dir)?;
+
+        let mut tests = Vec::new();
+        let mut test_indices = HashMap::new();
+        let mut subtasks: Vec<Subtask> = Vec::with_capacity(frontmatter.subtasks.len());
 
         let mut n = 1;
-        for (idx, subtask) in frontmatter.subtasks.iter().enumerate() {
-            for _ in 0..subtask.tests {
-                let (Ok(input), Ok(output)) = (
-                    fs::read_to_string(test_dir.join(format!("{n}.in"))),
-                    fs::read_to_string(test_dir.join(format!("{n}.out"))),
-                ) else {
-                    break;
+        for (idx, subtask) in frontmatter.subtasks.into_iter().enumerate() {
+            let mut selected = Vec::new();
+
+            for &include in subtask.includes.iter() {
+                if include == 0 || include > idx {
+                    return Err(LoadContestError::InvalidTask(
+                        "subtasks can only include preceding subtasks",
+                    ));
+                }
+
+                selected.extend(subtasks[include - 1].tests.iter().copied());
+            }
+
+            let names = match subtask.tests {
+                TestSelection::Count(count) => {
+                    let names = (n..n + count).map(|n| n.to_string()).collect();
+                    n += count;
+                    names
+                }
+                TestSelection::List(patterns) => {
+                    let mut names = Vec::new();
+
+                    for pattern in patterns {
+                        let pattern = match pattern {
+                            TestPattern::Number(n) => n.to_string(),
+                            TestPattern::Name(name) => name,
+                        };
+
+                        let matches: Vec<_> = test_names
+                            .iter()
+                            .filter(|name| wildcard_match(&pattern, name))
+                            .cloned()
+                            .collect();
+
+                        if matches.is_empty() {
+                            return Err(LoadContestError::NoMatchingTests(pattern));
+                        }
+
+                        names.extend(matches);
+                    }
+
+                    names
+                }
+            };
+
+            // Tests shared between subtasks are only loaded (and judged) once
+            for name in names {
+                let idx = match test_indices.get(&name) {
+                    Some(&idx) => idx,
+                    None => {
+                        tests.push(Test::load(&test_dir, &name)?);
+                        test_indices.insert(name, tests.len() - 1);
+                        tests.len() - 1
+                    }
                 };
 
-                n += 1;
+                selected.push(idx);
+            }
+
+            let mut seen = HashSet::new();
+            selected.retain(|idx| seen.insert(*idx));
+
+            // Everyone would be given the points of a subtask without tests
+            if selected.is_empty() {
+                return Err(LoadContestError::InvalidTask(
+                    "every subtask needs at least one test",
+                ));
+            }
 
-                tests.push(Test {
-                    subtask: idx + 1,
-                    input,
-                    output,
-                });
+            subtasks.push(Subtask {
+                points: subtask.points.unwrap_or(selected.len() as u32),
+                tests: selected,
+                includes: subtask.includes,
+                scoring: subtask.scoring,
+                constraints: subtask.constraints,
+            });
+        }
+
+        match (
+            frontmatter.kind,
+            &frontmatter.interactor,
+            &frontmatter.checker,
+        ) {
+            (TaskKind::Interactive, None, _) => {
+                return Err(LoadContestError::InvalidTask(
+                    "interactive task does not have an interactor",
+                ))
+            }
+            (TaskKind::Interactive, Some(_), Some(_)) => {
+                return Err(LoadContestError::InvalidTask(
+                    "interactive task cannot have a checker",
+                ))
+            }
+            (TaskKind::Batch | TaskKind::OutputOnly, Some(_), _) => {
+                return Err(LoadContestError::InvalidTask(
+                    "only interactive tasks can have an interactor",
+                ))
             }
+            _ => {}
+        }
+
+        let checker = frontmatter
+            .checker
+            .map(|checker| {
+                checker.compile(
+                    path,
+                    judge_config,
+                    rlimits,
+                    |language, source, files, build, run| {
+                        Checker::compile(judge_config, language, source, files, build, run)
+                    },
+                )
+            })
+            .transpose()?
+            .map(Arc::new);
+
+        let interactor = frontmatter
+            .interactor
+            .map(|interactor| {
+                interactor.compile(
+                    path,
+                    judge_config,
+                    rlimits,
+                    |language, source, files, build, run| {
+                        Interactor::compile(judge_config, language, source, files, build, run)
+                    },
+                )
+            })
+            .transpose()?
+            .map(Arc::new);
+
+        if !frontmatter.graders.is_empty() && frontmatter.kind != TaskKind::Batch {
+            return Err(LoadContestError::InvalidTask(
+                "only batch tasks can have graders",
+            ));
         }
 
+        let graders = frontmatter
+            .graders
+            .into_iter()
+            .map(|(language, grader)| {
+                let grader = grader.load(path, judge_config, &language)?;
+                Ok((language, Arc::new(grader)))
+            })
+            .collect::<Result<_, LoadContestError>>()?;
+
         Ok(Task {
             name: frontmatter.name,
+            path: Some(path.to_owned()),
             page,
             examples: frontmatter.examples,
-            subtasks: frontmatter.subtasks,
+            subtasks,
             tests,
             constraints: frontmatter.constraints,
             difficulty: frontmatter.difficulty,
+            kind: frontmatter.kind,
+            short_circuit: frontmatter.short_circuit,
+            comparison: frontmatter.comparison,
+            checker,
+            interactor,
+            graders,
+        })
+    }
+}
+
+impl Test {
+    fn load(test_dir: &Path, name: &str) -> Result<Self, LoadContestError> {
+        let (Ok(input), Ok(output)) = (
+            fs::read_to_string(test_dir.join(format!("{name}.in"))),
+            fs::read_to_string(test_dir.join(format!("{name}.out"))),
+        ) else {
+            return Err(LoadContestError::MissingTest(name.to_owned()));
+        };
+
+        let alternatives = (1..)
+            .map_while(|alt| fs::read_to_string(test_dir.join(format!("{name}.out.alt{alt}"))).ok())
+            .collect();
+
+        Ok(Test {
+            name: name.to_owned(),
+            input,
+            output,
+            alternatives,
+        })
+    }
+}
+
+/// Lists the names of the tests in a directory, with numbered tests first in numerical order.
+fn test_names(test_dir: &Path) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+
+    if test_dir.is_dir() {
+        for entry in fs::read_dir(test_dir)? {
+            let path = entry?.path();
+
+            if path.extension().is_some_and(|extension| extension == "in") {
+                if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                    names.push(name.to_owned());
+                }
+            }
+        }
+    }
+
+    names.sort_by_cached_key(|name| (name.parse::<u64>().map_err(|_| name.clone()), name.clone()));
+    Ok(names)
+}
+
+/// Matches a name against a pattern, where `*` matches any sequence of characters and `?` matches
+/// any single character.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<_>, Vec<_>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+impl ProgramFrontmatter {
+    fn compile<T>(
+        self,
+        path: &Path,
+        judge_config: &JudgeConfig,
+        rlimits: ContestResourceLimits,
+        compile: impl FnOnce(
+            &Language,
+            Vec<u8>,
+            Vec<(String, Vec<u8>)>,
+            ResourceLimits,
+            ResourceLimits,
+        ) -> JudgeResult<T>,
+    ) -> Result<T, LoadContestError> {
+        tracing::trace!("compiling judge program {}", self.source);
+        let language = judge_config
+            .language(&self.language)
+            .map_err(LoadContestError::Program)?;
+
+        let source = fs::read(path.join(&self.source))?;
+        let files = self
+            .files
+            .into_iter()
+            .map(|file| {
+                let contents = fs::read(path.join(&file))?;
+                Ok((file, contents))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        compile(
+            language,
+            source,
+            files,
+            rlimits.build,
+            self.rlimits.unwrap_or(rlimits.run),
+        )
+        .map_err(LoadContestError::Program)
+    }
+}
+
+impl GraderFrontmatter {
+    fn load(
+        self,
+        path: &Path,
+        judge_config: &JudgeConfig,
+        language: &str,
+    ) -> Result<Grader, LoadContestError> {
+        let language = judge_config
+            .language(language)
+            .map_err(LoadContestError::Program)?;
+
+        // Otherwise the submission would be compiled or run next to the grader's sources
+        let (Some(build), Some(grader)) = (&language.build, &language.grader) else {
+            return Err(LoadContestError::InvalidTask(
+                "grader language does not have a build step and a grader command",
+            ));
+        };
+        if !build.takes_sources() || !grader.takes_sources() {
+            return Err(LoadContestError::InvalidTask(
+                "grader language does not have a {sources} placeholder in its build and grader commands",
+            ));
+        }
+
+        let files = self
+            .sources
+            .iter()
+            .chain(self.files.iter())
+            .map(|file| {
+                let contents = fs::read(path.join(file))?;
+                Ok((file.clone(), contents))
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Grader {
+            sources: self.sources,
+            files,
         })
     }
 }
@@ -174,3 +552,46 @@ mod defaults {
         100
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_match("12", "12"));
+        assert!(!wildcard_match("1", "12"));
+        assert!(wildcard_match("large-*", "large-07"));
+        assert!(wildcard_match("*-0?", "small-03"));
+        assert!(!wildcard_match("*-0?", "small-13"));
+        assert!(wildcard_match("a*b*c", "aXbYbZc"));
+        assert!(!wildcard_match("a*b*c", "aXbYbZ"));
+        assert!(wildcard_match("*", ""));
+    }
+
+    #[test]
+    fn subtasks_without_tests() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("tests")).unwrap();
+        fs::write(dir.path().join("tests/1.in"), "1").unwrap();
+        fs::write(dir.path().join("tests/1.out"), "1").unwrap();
+        fs::write(
+            dir.path().join("task.md"),
+            "---\nname: Task\nsubtasks:\n  - tests: [1]\n  - points: 10\n---\n",
+        )
+        .unwrap();
+
+        let judge_config: JudgeConfig = toml::from_str("languages = []").unwrap();
+        let rlimits: ContestResourceLimits = toml::from_str(
+            "build = { time = 1, memory = \"256MiB\" }\nrun = { time = 1, memory = \"256MiB\" }",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            Task::load(dir.path(), &judge_config, rlimits),
+            Err(LoadContestError::InvalidTask(
+                "every subtask needs at least one test"
+            ))
+        ));
+    }
+}
@@ -1,47 +1,205 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use axum_typed_multipart::TryFromMultipart;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-pub use self::{grade::*, run::run, sandbox::*};
+pub use self::{
+    checker::Checker,
+    compare::{Comparison, Tolerance},
+    grade::*,
+    interactor::Interactor,
+    outputs::{check_outputs, collect_outputs},
+    program::ProgramSource,
+    run::{run, Progress, TestResult},
+    sandbox::*,
+};
 
+mod checker;
+mod compare;
 mod grade;
+mod interactor;
+mod outputs;
+mod program;
+pub mod remote;
 mod run;
 mod sandbox;
 
+#[cfg(test)]
+pub(crate) use self::run::tests::task as test_task;
+
 pub type JudgeResult<T> = Result<T, JudgeError>;
 
 #[derive(Debug, Error)]
 pub enum JudgeError {
     #[error("failed to compile submission, stderr: {0}")]
     CompileError(String),
-    #[error("failed to process submission output as UTF-8: {0}")]
-    Utf8(#[from] std::str::Utf8Error),
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
     #[error("unknown language: {0}")]
     UnknownLanguage(String),
+    #[error("task does not support language: {0}")]
+    UnsupportedLanguage(String),
+    #[error("checker error: {0}")]
+    Checker(String),
+    #[error("interactor error: {0}")]
+    Interactor(String),
+    #[error("failed to compile grader: {0}")]
+    Grader(String),
+    #[error("failed to read zip archive: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("refusing to judge as root without a pool of users to run submissions as")]
+    RunningAsRoot,
+    #[error("running as the pool of users needs root or CAP_CHOWN, CAP_SETUID and CAP_SETGID")]
+    UsersNotPermitted,
+    #[error("runs could read the test data of tasks without Landlock or namespaces for runs")]
+    Unprotected,
+    #[error("remote worker error: {error}")]
+    Worker { error: String, transient: bool },
+    #[error("judging panicked: {0}")]
+    Panicked(String),
+}
+
+impl JudgeError {
+    /// Whether judging again later may succeed, rather than failing the same way.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            JudgeError::Io(_) => true,
+            JudgeError::Worker { transient, .. } => *transient,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 pub struct Config {
     #[serde(alias = "language")]
     pub languages: Vec<Language>,
+    /// A cgroup v2 directory delegated to the judge, which must not contain any processes itself.
+    /// Each run is placed in its own child cgroup for accurate memory accounting, otherwise
+    /// memory is only limited with rlimits.
+    #[serde(default)]
+    pub cgroup: Option<PathBuf>,
+    /// Paths outside of the sandbox which submissions can read and execute, such as language
+    /// runtimes. Everything else is inaccessible to submissions if the kernel supports Landlock,
+    /// and runs which try to open it are judged as a security violation.
+    #[serde(default = "default_readable_paths")]
+    pub readable_paths: Vec<PathBuf>,
+    /// Profiles whose commands run in new user, pid, network, IPC, UTS and mount namespaces,
+    /// with the sandbox directory as the root and the readable paths mounted read-only.
+    #[serde(default)]
+    pub namespaces: Vec<Profile>,
+    /// Unprivileged users to run sandboxed commands as, which is required when the judge runs as
+    /// root. Otherwise commands run as the same user as the judge.
+    #[serde(default)]
+    pub users: Option<UserPool>,
+    /// What happens when a submission makes a system call which is not allowed. Trapping or
+    /// killing judges the submission as a security violation, which needs Linux 5.0.
+    #[serde(default)]
+    pub seccomp: SeccompMode,
+    /// How many submissions are judged at once, by default one per CPU.
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+}
+
+fn default_workers() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+fn default_readable_paths() -> Vec<PathBuf> {
+    [
+        "/bin",
+        "/lib",
+        "/lib64",
+        "/usr",
+        "/etc/ld.so.cache",
+        "/etc/localtime",
+        "/dev/null",
+        "/dev/urandom",
+        "/dev/zero",
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .collect()
+}
+
+impl Config {
+    /// Falls back to rlimits if the configured cgroup cannot be used.
+    pub fn check_cgroup(&mut self) {
+        if let Some(root) = &self.cgroup {
+            if let Err(e) = sandbox::cgroup::check(root) {
+                tracing::warn!(
+                    "cgroup {} is unavailable, using rlimits: {e}",
+                    root.display()
+                );
+                self.cgroup = None;
+            }
+        }
+    }
+
+    /// Runs commands without namespaces if the judge cannot create them.
+    pub fn check_namespaces(&mut self) {
+        if !self.namespaces.is_empty() {
+            if let Err(e) = sandbox::namespace::check(self.users.map(|users| users.judge())) {
+                tracing::warn!("namespaces are unavailable, running without them: {e}");
+                self.namespaces.clear();
+            }
+        }
+    }
+
+    /// Refuses to judge as root unless sandboxed commands can run as other users, or with a pool
+    /// of users that the judge cannot switch to.
+    pub fn check_users(&self) -> JudgeResult<()> {
+        match self.users {
+            Some(users) => {
+                users.check()?;
+                if users.permitted()? {
+                    Ok(())
+                } else {
+                    Err(JudgeError::UsersNotPermitted)
+                }
+            }
+            None if unsafe { libc::geteuid() } == 0 => Err(JudgeError::RunningAsRoot),
+            None => Ok(()),
+        }
+    }
+
+    /// Refuses to judge tasks from the contest directory if runs could read their test data.
+    pub fn check_protection(&self) -> JudgeResult<()> {
+        if sandbox::landlock::supported() || self.namespaces.contains(&Profile::Run) {
+            Ok(())
+        } else {
+            Err(JudgeError::Unprotected)
+        }
+    }
+
+    pub fn language(&self, name: &str) -> JudgeResult<&Language> {
+        self.languages
+            .iter()
+            .find(|language| language.name == name)
+            .ok_or_else(|| JudgeError::UnknownLanguage(name.to_owned()))
+    }
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, TryFromMultipart)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize, TryFromMultipart)]
 pub struct Submission {
     pub code: String,
     pub language: String,
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 pub struct Language {
     pub name: String,
     pub filename: String,
     pub build: Option<Command>,
+    /// Compiles the sources of a task's grader on their own into `grader.o`, which `build` then
+    /// compiles submissions with, e.g. `g++ {sources} -r -o grader.o`
+    pub grader: Option<Command>,
     pub run: Command,
+    /// The system calls that runs may make, as `seccomp.extend = [...]` or `seccomp.replace =
+    /// [...]` to extend or replace the default allowlist
+    #[serde(default)]
+    pub seccomp: SyscallAllowlist,
 }
 
 impl Language {
@@ -0,0 +1,73 @@
+use super::{program::*, sandbox::Profile, *};
+
+#[derive(Debug)]
+pub struct Checker(Program);
+
+impl Checker {
+    #[tracing::instrument(skip(config, source, files), err)]
+    pub fn compile(
+        config: &Config,
+        language: &Language,
+        source: impl AsRef<[u8]>,
+        files: impl IntoIterator<Item = (String, Vec<u8>)>,
+        build_rlimits: ResourceLimits,
+        rlimits: ResourceLimits,
+    ) -> JudgeResult<Self> {
+        Program::compile(config, language, source, files, build_rlimits, rlimits).map(Checker)
+    }
+
+    pub fn source(&self) -> &ProgramSource {
+        &self.0.source
+    }
+
+    /// Runs the checker with the paths of the test input, the contestant output and the
+    /// reference answer as arguments, following the testlib.h convention.
+    pub fn check(&self, input: &str, output: &[u8], answer: &str) -> JudgeResult<Judgement> {
+        let Checker(program) = self;
+        let files = [
+            ("in", input.as_bytes()),
+            ("out", output),
+            ("ans", answer.as_bytes()),
+        ];
+
+        program.with_files(&files, |paths| {
+            let command = program.command.with_args(paths);
+            let output =
+                program
+                    .sandbox
+                    .exec(&command, Some(&[]), program.rlimits, Profile::Judge)?;
+
+            testlib_judgement(&output).map_err(JudgeError::Checker)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn testlib_partial_scores() {
+        let config: Config = toml::from_str(
+            "[[language]]\nname = \"sh\"\nfilename = \"checker.sh\"\nrun = \"/bin/sh checker.sh\"",
+        )
+        .unwrap();
+        let rlimits: ResourceLimits = toml::from_str("time = 1\nmemory = \"256MiB\"").unwrap();
+
+        // What testlib's `quitp(0.25, "close")` writes before exiting with `_points`
+        let checker = Checker::compile(
+            &config,
+            config.language("sh").unwrap(),
+            "echo 'points 0.25 close' >&2; exit 7",
+            [],
+            rlimits,
+            rlimits,
+        )
+        .unwrap();
+        let judgement = checker.check("1", b"2", "3").unwrap();
+
+        assert_eq!(judgement.verdict, Verdict::PartialScore);
+        assert_eq!(judgement.score, 0.25);
+        assert_eq!(judgement.message.as_deref(), Some("close"));
+    }
+}
@@ -0,0 +1,209 @@
+use serde::{Deserialize, Serialize};
+
+/// How contestant output is compared against the reference output of a test.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ComparisonFrontmatter", into = "ComparisonFrontmatter")]
+pub enum Comparison {
+    /// Exact comparison, ignoring leading and trailing whitespace
+    #[default]
+    Exact,
+    /// Whitespace-separated tokens must match
+    Tokens,
+    /// Lines must match, ignoring trailing whitespace and trailing empty lines
+    Lines,
+    /// Whitespace-separated tokens must match, ignoring case
+    CaseInsensitive,
+    /// Lines must match in any order, ignoring trailing whitespace and empty lines
+    UnorderedLines,
+    /// Whitespace-separated tokens must match, with numbers compared within a tolerance
+    Float(Tolerance),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tolerance {
+    #[serde(default = "defaults::epsilon")]
+    pub absolute: f64,
+    #[serde(default = "defaults::epsilon")]
+    pub relative: f64,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance {
+            absolute: defaults::epsilon(),
+            relative: defaults::epsilon(),
+        }
+    }
+}
+
+// Allows both `comparison: float` and `comparison: { float: { absolute: 1e-9 } }`
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ComparisonFrontmatter {
+    Mode(Mode),
+    Float { float: Tolerance },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Mode {
+    Exact,
+    Tokens,
+    Lines,
+    CaseInsensitive,
+    UnorderedLines,
+    Float,
+}
+
+impl From<ComparisonFrontmatter> for Comparison {
+    fn from(frontmatter: ComparisonFrontmatter) -> Self {
+        match frontmatter {
+            ComparisonFrontmatter::Mode(mode) => match mode {
+                Mode::Exact => Comparison::Exact,
+                Mode::Tokens => Comparison::Tokens,
+                Mode::Lines => Comparison::Lines,
+                Mode::CaseInsensitive => Comparison::CaseInsensitive,
+                Mode::UnorderedLines => Comparison::UnorderedLines,
+                Mode::Float => Comparison::Float(Tolerance::default()),
+            },
+            ComparisonFrontmatter::Float { float } => Comparison::Float(float),
+        }
+    }
+}
+
+impl From<Comparison> for ComparisonFrontmatter {
+    fn from(comparison: Comparison) -> Self {
+        ComparisonFrontmatter::Mode(match comparison {
+            Comparison::Exact => Mode::Exact,
+            Comparison::Tokens => Mode::Tokens,
+            Comparison::Lines => Mode::Lines,
+            Comparison::CaseInsensitive => Mode::CaseInsensitive,
+            Comparison::UnorderedLines => Mode::UnorderedLines,
+            Comparison::Float(float) => return ComparisonFrontmatter::Float { float },
+        })
+    }
+}
+
+impl Comparison {
+    pub fn matches(&self, output: &str, answer: &str) -> bool {
+        match self {
+            Comparison::Exact => output.trim() == answer.trim(),
+            Comparison::Tokens => output.split_whitespace().eq(answer.split_whitespace()),
+            Comparison::Lines => lines(output).eq(lines(answer)),
+            Comparison::CaseInsensitive => output
+                .split_whitespace()
+                .map(str::to_lowercase)
+                .eq(answer.split_whitespace().map(str::to_lowercase)),
+            Comparison::UnorderedLines => {
+                let sorted = |s| {
+                    let mut lines: Vec<_> = lines(s).filter(|line| !line.is_empty()).collect();
+                    lines.sort_unstable();
+                    lines
+                };
+
+                sorted(output) == sorted(answer)
+            }
+            Comparison::Float(tolerance) => {
+                let (mut output, mut answer) =
+                    (output.split_whitespace(), answer.split_whitespace());
+
+                loop {
+                    match (output.next(), answer.next()) {
+                        (Some(a), Some(b)) if a == b => continue,
+                        (Some(a), Some(b)) => match (a.parse::<f64>(), b.parse::<f64>()) {
+                            (Ok(a), Ok(b)) if tolerance.matches(a, b) => continue,
+                            _ => return false,
+                        },
+                        (None, None) => return true,
+                        _ => return false,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Tolerance {
+    fn matches(&self, output: f64, answer: f64) -> bool {
+        let error = (output - answer).abs();
+        error <= self.absolute || error <= self.relative * answer.abs()
+    }
+}
+
+fn lines(s: &str) -> impl Iterator<Item = &str> {
+    s.trim_end().lines().map(str::trim_end)
+}
+
+mod defaults {
+    pub fn epsilon() -> f64 {
+        1e-6
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modes() {
+        assert!(Comparison::Exact.matches("1 2\n3\n", "1 2\n3"));
+        assert!(!Comparison::Exact.matches("1  2\n3", "1 2\n3"));
+
+        assert!(Comparison::Tokens.matches("1  2\n3", "1 2 3\n"));
+        assert!(!Comparison::Tokens.matches("1 2", "1 2 3"));
+
+        assert!(Comparison::Lines.matches("a b  \nc\n\n", "a b\nc"));
+        assert!(!Comparison::Lines.matches("a\nb", "a b"));
+
+        assert!(Comparison::CaseInsensitive.matches("YES\nno", "yes NO"));
+        assert!(!Comparison::CaseInsensitive.matches("yes", "no"));
+
+        assert!(Comparison::UnorderedLines.matches("b\na\n\nc", "a\nb\nc\n"));
+        assert!(!Comparison::UnorderedLines.matches("a\na\nb", "a\nb\nb"));
+    }
+
+    #[test]
+    fn float() {
+        let float = Comparison::Float(Tolerance::default());
+
+        assert!(float.matches("0.5000000001", "0.5"));
+        assert!(float.matches("1000000.5", "1000000"));
+        assert!(float.matches("YES 0.3333333", "YES 0.333333333"));
+        assert!(!float.matches("0.51", "0.5"));
+        assert!(!float.matches("NO 0.5", "YES 0.5"));
+        assert!(!float.matches("0.5", "0.5 0.5"));
+
+        let strict = Comparison::Float(Tolerance {
+            absolute: 0.0,
+            relative: 0.0,
+        });
+        assert!(!strict.matches("0.5000000001", "0.5"));
+    }
+
+    #[test]
+    fn deserialize() {
+        #[derive(Deserialize)]
+        struct Frontmatter {
+            comparison: Comparison,
+        }
+
+        let parse = |s| serde_yaml::from_str::<Frontmatter>(s).unwrap().comparison;
+
+        assert_eq!(
+            parse("comparison: unordered-lines"),
+            Comparison::UnorderedLines
+        );
+        assert_eq!(
+            parse("comparison: float"),
+            Comparison::Float(Tolerance::default())
+        );
+        assert_eq!(
+            parse("comparison:\n  float:\n    absolute: 1e-9"),
+            Comparison::Float(Tolerance {
+                absolute: 1e-9,
+                relative: 1e-6
+            })
+        );
+    }
+}
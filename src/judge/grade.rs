@@ -1,50 +1,164 @@
 use std::{fmt, str::FromStr};
 
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use yansi::Paint;
 
 use super::{run::TestResult, sandbox::ResourceUsage};
-use crate::contest::Task;
+use crate::contest::{Subtask, Task};
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct GradedTask {
     pub verdict: Verdict,
     pub score: u32,
     pub subtasks: Vec<GradedSubtask>,
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct GradedSubtask {
     pub verdict: Verdict,
     pub score: u32,
     pub tests: Vec<GradedTest>,
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct GradedTest {
+    /// Index into the tests of the task
+    pub test: usize,
     pub verdict: Verdict,
-    pub score: u32,
+    /// Fraction of the test that was solved, between 0 and 1
+    pub score: f64,
+    pub message: Option<String>,
     pub resource_usage: Option<ResourceUsage>,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
 }
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum Verdict {
+    /// Queued or being judged
+    Pending,
+    /// The judge failed to judge the submission, which is judged again once fixed
+    JudgeError,
+    SecurityViolation,
     WrongAnswer,
     TimeLimitExceeded,
     MemoryLimitExceeded,
+    OutputLimitExceeded,
     PartialScore,
     CompileError,
     RuntimeError,
+    Skipped,
     Accepted,
 }
 
+/// How the scores of the tests in a subtask are combined into the score of the subtask.
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scoring {
+    /// Full points only if every test is accepted
+    AllOrNothing,
+    /// Points are split evenly between tests, including partial scores
+    Sum,
+    /// Points are scaled by the lowest test score, which is the same as all-or-nothing when
+    /// there are no partial scores
+    #[default]
+    Minimum,
+    /// Points are scaled by the fraction of tests that are accepted
+    Proportional,
+}
+
+impl Scoring {
+    /// Computes the fraction of the subtask's points that are awarded.
+    pub fn fraction(&self, scores: &[f64]) -> f64 {
+        if scores.is_empty() {
+            return 0.0;
+        }
+
+        let count = scores.len() as f64;
+
+        match self {
+            Scoring::AllOrNothing => {
+                if scores.iter().all(|&score| score >= 1.0) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Scoring::Sum => scores.iter().sum::<f64>() / count,
+            Scoring::Minimum => scores.iter().copied().fold(1.0, f64::min),
+            Scoring::Proportional => {
+                scores.iter().filter(|&&score| score >= 1.0).count() as f64 / count
+            }
+        }
+    }
+
+    /// Whether a test with this score means the subtask cannot be awarded any points.
+    pub fn decided_by(&self, score: f64) -> bool {
+        match self {
+            Scoring::AllOrNothing => score < 1.0,
+            Scoring::Minimum => score <= 0.0,
+            Scoring::Sum | Scoring::Proportional => false,
+        }
+    }
+}
+
+/// The verdict, score and message given to the output of a single test.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Judgement {
+    pub verdict: Verdict,
+    pub score: f64,
+    pub message: Option<String>,
+}
+
+impl Judgement {
+    pub fn partial(score: f64, message: Option<String>) -> Self {
+        let score = score.clamp(0.0, 1.0);
+
+        let verdict = if score == 1.0 {
+            Verdict::Accepted
+        } else if score == 0.0 {
+            Verdict::WrongAnswer
+        } else {
+            Verdict::PartialScore
+        };
+
+        Judgement {
+            verdict,
+            score,
+            message,
+        }
+    }
+
+    pub fn with_message(self, message: Option<String>) -> Self {
+        Judgement { message, ..self }
+    }
+}
+
+impl From<Verdict> for Judgement {
+    fn from(verdict: Verdict) -> Self {
+        Judgement {
+            verdict,
+            score: if verdict == Verdict::Accepted {
+                1.0
+            } else {
+                0.0
+            },
+            message: None,
+        }
+    }
+}
+
 impl Verdict {
     pub fn fmt_colored(&self) -> impl fmt::Display + '_ {
         let paint = match self {
-            Verdict::WrongAnswer => Paint::red,
-            Verdict::TimeLimitExceeded | Verdict::MemoryLimitExceeded => Paint::magenta,
+            Verdict::JudgeError | Verdict::SecurityViolation | Verdict::WrongAnswer => Paint::red,
+            Verdict::TimeLimitExceeded
+            | Verdict::MemoryLimitExceeded
+            | Verdict::OutputLimitExceeded => Paint::magenta,
             Verdict::PartialScore => Paint::blue,
             Verdict::CompileError | Verdict::RuntimeError => Paint::yellow,
+            Verdict::Pending | Verdict::Skipped => Paint::white,
             Verdict::Accepted => Paint::green,
         };
 
@@ -55,12 +169,17 @@ impl Verdict {
 impl fmt::Display for Verdict {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Verdict::Pending => "Pending",
+            Verdict::JudgeError => "Judge Error",
+            Verdict::SecurityViolation => "Security Violation",
             Verdict::CompileError => "Compile Error",
             Verdict::RuntimeError => "Runtime Error",
             Verdict::WrongAnswer => "Wrong Answer",
             Verdict::TimeLimitExceeded => "Time Limit Exceeded",
             Verdict::MemoryLimitExceeded => "Memory Limit Exceeded",
+            Verdict::OutputLimitExceeded => "Output Limit Exceeded",
             Verdict::PartialScore => "Partial Score",
+            Verdict::Skipped => "Skipped",
             Verdict::Accepted => "Accepted",
         }
         .fmt(f)
@@ -76,12 +195,17 @@ impl FromStr for Verdict {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(match s.to_lowercase().as_str() {
+            "pending" => Verdict::Pending,
+            "judge error" => Verdict::JudgeError,
+            "security violation" => Verdict::SecurityViolation,
             "compile error" => Verdict::CompileError,
             "runtime error" => Verdict::RuntimeError,
             "wrong answer" => Verdict::WrongAnswer,
             "time limit exceeded" => Verdict::TimeLimitExceeded,
             "memory limit exceeded" => Verdict::MemoryLimitExceeded,
+            "output limit exceeded" => Verdict::OutputLimitExceeded,
             "partial score" => Verdict::PartialScore,
+            "skipped" => Verdict::Skipped,
             "accepted" => Verdict::Accepted,
             _ => return Err(InvalidVerdict(s.to_owned())),
         })
@@ -95,39 +219,8 @@ pub fn grade(task: &Task, results: &[TestResult]) -> GradedTask {
         subtasks: Vec::with_capacity(task.subtasks.len()),
     };
 
-    let mut iter = results.iter();
-
     for subtask in task.subtasks.iter() {
-        let mut subtask_grade = GradedSubtask {
-            verdict: Verdict::Accepted,
-            score: 0,
-            tests: Vec::with_capacity(subtask.tests),
-        };
-
-        for TestResult {
-            verdict,
-            resource_usage,
-        } in iter.by_ref().copied().take(subtask.tests)
-        {
-            let score = if let Verdict::Accepted = verdict {
-                1
-            } else {
-                0
-            };
-
-            subtask_grade.score += score;
-            subtask_grade.verdict = subtask_grade.verdict.min(verdict);
-            subtask_grade.tests.push(GradedTest {
-                verdict,
-                score,
-                resource_usage,
-            })
-        }
-
-        // TODO: replace this with a better scoring system
-        if subtask_grade.verdict != Verdict::Accepted {
-            subtask_grade.score = 0;
-        }
+        let subtask_grade = grade_subtask(subtask, results);
 
         grade.verdict = grade.verdict.min(subtask_grade.verdict);
         grade.score += subtask_grade.score;
@@ -136,3 +229,70 @@ pub fn grade(task: &Task, results: &[TestResult]) -> GradedTask {
 
     grade
 }
+
+pub fn grade_subtask(subtask: &Subtask, results: &[TestResult]) -> GradedSubtask {
+    let mut grade = GradedSubtask {
+        verdict: Verdict::Accepted,
+        score: 0,
+        tests: Vec::with_capacity(subtask.tests.len()),
+    };
+
+    // Tests shared between subtasks are judged once, but graded in every subtask
+    for &test in subtask.tests.iter() {
+        let TestResult {
+            verdict,
+            score,
+            message,
+            resource_usage,
+            exit_code,
+            signal,
+        } = results[test].clone();
+
+        grade.verdict = grade.verdict.min(verdict);
+        grade.tests.push(GradedTest {
+            test,
+            verdict,
+            score,
+            message,
+            resource_usage,
+            exit_code,
+            signal,
+        })
+    }
+
+    let scores: Vec<_> = grade.tests.iter().map(|test| test.score).collect();
+    grade.score = (subtask.points as f64 * subtask.scoring.fraction(&scores)).round() as u32;
+
+    grade
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scoring() {
+        let scores = [1.0, 0.5, 1.0, 0.0];
+
+        assert_eq!(Scoring::AllOrNothing.fraction(&scores), 0.0);
+        assert_eq!(Scoring::AllOrNothing.fraction(&[1.0, 1.0]), 1.0);
+        assert_eq!(Scoring::Sum.fraction(&scores), 0.625);
+        assert_eq!(Scoring::Minimum.fraction(&scores), 0.0);
+        assert_eq!(Scoring::Minimum.fraction(&[1.0, 0.5]), 0.5);
+        assert_eq!(Scoring::Proportional.fraction(&scores), 0.5);
+        assert_eq!(Scoring::Sum.fraction(&[]), 0.0);
+    }
+
+    #[test]
+    fn verdicts() {
+        for verdict in [
+            Verdict::Pending,
+            Verdict::JudgeError,
+            Verdict::SecurityViolation,
+        ] {
+            assert_eq!(verdict.to_string().parse::<Verdict>().unwrap(), verdict);
+        }
+
+        assert!("Internal Error".parse::<Verdict>().is_err());
+    }
+}
@@ -0,0 +1,62 @@
+use super::{program::*, *};
+
+#[derive(Debug)]
+pub struct Interactor(Program);
+
+impl Interactor {
+    #[tracing::instrument(skip(config, source, files), err)]
+    pub fn compile(
+        config: &Config,
+        language: &Language,
+        source: impl AsRef<[u8]>,
+        files: impl IntoIterator<Item = (String, Vec<u8>)>,
+        build_rlimits: ResourceLimits,
+        rlimits: ResourceLimits,
+    ) -> JudgeResult<Self> {
+        Program::compile(config, language, source, files, build_rlimits, rlimits).map(Interactor)
+    }
+
+    pub fn source(&self) -> &ProgramSource {
+        &self.0.source
+    }
+
+    /// Runs a submission with its stdin and stdout connected to the interactor. The interactor
+    /// receives the paths of the test input, an output file and the reference answer as
+    /// arguments, following the testlib.h convention.
+    pub fn interact(
+        &self,
+        sandbox: &Sandbox,
+        command: &Command,
+        rlimits: ResourceLimits,
+        input: &str,
+        answer: &str,
+    ) -> JudgeResult<(Output, Judgement)> {
+        let Interactor(program) = self;
+        let files = [
+            ("in", input.as_bytes()),
+            ("out", &[][..]),
+            ("ans", answer.as_bytes()),
+        ];
+
+        program.with_files(&files, |paths| {
+            let (output, interactor_output) = sandbox.interact(
+                command,
+                rlimits,
+                &program.sandbox,
+                &program.command.with_args(paths),
+                program.rlimits,
+            )?;
+
+            // The interactor was killed along with the submission, so it has no judgement
+            let judgement = if output.output_limit_exceeded {
+                Verdict::OutputLimitExceeded.into()
+            } else if output.timeout.is_some() {
+                Verdict::TimeLimitExceeded.into()
+            } else {
+                testlib_judgement(&interactor_output).map_err(JudgeError::Interactor)?
+            };
+
+            Ok((output, judgement))
+        })
+    }
+}
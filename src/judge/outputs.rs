@@ -0,0 +1,157 @@
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use rayon::prelude::*;
+
+use super::{run::judge_output, *};
+use crate::contest::Task;
+
+/// Judges the uploaded outputs of an output-only task, keyed by test index. Nothing is built or
+/// run, tests without an uploaded output are given a wrong answer and outputs longer than `limit`
+/// bytes exceed the output limit.
+#[tracing::instrument(skip_all, err)]
+pub fn check_outputs(
+    task: &Task,
+    outputs: &HashMap<usize, Vec<u8>>,
+    limit: u64,
+) -> JudgeResult<Vec<TestResult>> {
+    task.tests
+        .par_iter()
+        .enumerate()
+        .map(|(idx, test)| {
+            let judgement = match outputs.get(&idx) {
+                Some(output) if output.len() as u64 > limit => {
+                    Judgement::from(Verdict::OutputLimitExceeded)
+                }
+                Some(output) => judge_output(task, test, output)?,
+                None => Judgement::from(Verdict::WrongAnswer)
+                    .with_message(Some(String::from("no output was submitted"))),
+            };
+
+            let Judgement {
+                verdict,
+                score,
+                message,
+            } = judgement;
+
+            tracing::trace!(
+                "[{}/{}] {}",
+                idx + 1,
+                task.tests.len(),
+                verdict.fmt_colored()
+            );
+            Ok(TestResult {
+                verdict,
+                score,
+                message,
+                resource_usage: None,
+                exit_code: None,
+                signal: None,
+            })
+        })
+        .collect()
+}
+
+/// Matches uploaded files to the tests of a task by name, so `1.out` (or just `1`) is the output
+/// for test `1`. Zip archives are unpacked and their files matched in the same way.
+///
+/// Only the first `limit + 1` bytes of each file are kept, which is enough for [`check_outputs`]
+/// to tell that it is too long, and unpacking stops once the archives have given as much as the
+/// outputs of every test could take up.
+pub fn collect_outputs(
+    task: &Task,
+    files: impl IntoIterator<Item = (String, Vec<u8>)>,
+    limit: u64,
+) -> JudgeResult<HashMap<usize, Vec<u8>>> {
+    let mut outputs = HashMap::new();
+    let mut unpacked = 0u64;
+    let max_unpacked = (limit + 1).saturating_mul(task.tests.len() as u64);
+
+    for (filename, mut contents) in files {
+        if Path::new(&filename)
+            .extension()
+            .is_some_and(|ext| ext == "zip")
+        {
+            let mut archive = zip::ZipArchive::new(Cursor::new(contents))?;
+
+            for idx in 0..archive.len() {
+                let file = archive.by_index(idx)?;
+
+                if file.is_dir() {
+                    continue;
+                }
+
+                let filename = file.name().to_owned();
+                let Some(test) = find_test(task, &filename) else {
+                    continue;
+                };
+
+                if unpacked >= max_unpacked {
+                    tracing::debug!("ignoring output {filename:?} past the total size limit");
+                    continue;
+                }
+
+                let mut contents = Vec::new();
+                file.take(limit + 1).read_to_end(&mut contents)?;
+                unpacked += contents.len() as u64;
+
+                outputs.insert(test, contents);
+            }
+        } else if let Some(test) = find_test(task, &filename) {
+            contents.truncate((limit + 1).try_into().unwrap_or(usize::MAX));
+            outputs.insert(test, contents);
+        }
+    }
+
+    Ok(outputs)
+}
+
+fn find_test(task: &Task, filename: &str) -> Option<usize> {
+    let path = Path::new(filename);
+    let names = [path.file_name(), path.file_stem()];
+
+    let test = names.into_iter().flatten().find_map(|name| {
+        task.tests
+            .iter()
+            .position(|test| name.to_str() == Some(test.name.as_str()))
+    });
+
+    if test.is_none() {
+        tracing::debug!("ignoring output {filename:?} that does not match any test");
+    }
+
+    test
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::{write::FileOptions, ZipWriter};
+
+    use super::*;
+    use crate::judge::run::tests::task;
+
+    #[test]
+    fn capped_outputs() {
+        let task = task(&[&[0, 1]], 2);
+
+        // A highly compressible entry much larger than the limit, as in a zip bomb
+        let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+        archive.start_file("0.out", FileOptions::default()).unwrap();
+        archive.write_all(&vec![b'0'; 1 << 20]).unwrap();
+        archive.start_file("1.out", FileOptions::default()).unwrap();
+        archive.write_all(b"1").unwrap();
+        let archive = archive.finish().unwrap().into_inner();
+
+        let outputs = collect_outputs(&task, [(String::from("outputs.zip"), archive)], 16).unwrap();
+        assert_eq!(outputs[&0].len(), 17);
+        assert_eq!(outputs[&1], b"1");
+
+        let results = check_outputs(&task, &outputs, 16).unwrap();
+        assert_eq!(results[0].verdict, Verdict::OutputLimitExceeded);
+    }
+}
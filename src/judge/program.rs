@@ -0,0 +1,193 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde::Serialize;
+
+use super::*;
+
+// Exit codes used by testlib.h
+const EXIT_OK: i32 = 0;
+const EXIT_WRONG_ANSWER: i32 = 1;
+const EXIT_PRESENTATION_ERROR: i32 = 2;
+const EXIT_FAIL: i32 = 3;
+const EXIT_POINTS: i32 = 7;
+
+/// A task-supplied program (e.g. a checker or interactor), compiled once and kept in its
+/// own sandbox for the lifetime of the contest.
+#[derive(Debug)]
+pub(super) struct Program {
+    pub sandbox: Sandbox,
+    pub command: Command,
+    pub rlimits: ResourceLimits,
+    pub source: ProgramSource,
+    counter: AtomicUsize,
+}
+
+/// What a program was compiled from, so that remote workers can compile it themselves.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProgramSource {
+    pub language: String,
+    pub source: Vec<u8>,
+    pub files: Vec<(String, Vec<u8>)>,
+    pub build_rlimits: ResourceLimits,
+    pub rlimits: ResourceLimits,
+}
+
+impl Program {
+    pub fn compile(
+        config: &Config,
+        language: &Language,
+        source: impl AsRef<[u8]>,
+        files: impl IntoIterator<Item = (String, Vec<u8>)>,
+        build_rlimits: ResourceLimits,
+        rlimits: ResourceLimits,
+    ) -> JudgeResult<Self> {
+        let source = ProgramSource {
+            language: language.name.clone(),
+            source: source.as_ref().to_vec(),
+            files: files.into_iter().collect(),
+            build_rlimits,
+            rlimits,
+        };
+
+        let sandbox = Sandbox::for_judge(config, language)?;
+        sandbox.write(&language.filename, &source.source)?;
+
+        for (path, contents) in source.files.iter() {
+            sandbox.write(path, contents)?;
+        }
+
+        let sources = [language.filename.clone()];
+
+        if let Some(command) = &language.build {
+            run::build(&sandbox, &command.with_sources(&sources), build_rlimits)?;
+        }
+
+        Ok(Program {
+            sandbox,
+            command: language.run.with_sources(&sources),
+            rlimits,
+            source,
+            counter: AtomicUsize::new(0),
+        })
+    }
+
+    /// Writes files into the sandbox under unique names, so that concurrent invocations do not
+    /// clobber each other. The files are removed once `f` returns.
+    pub fn with_files<T>(
+        &self,
+        files: &[(&str, &[u8])],
+        f: impl FnOnce(&[String]) -> JudgeResult<T>,
+    ) -> JudgeResult<T> {
+        let n = self.counter.fetch_add(1, Ordering::Relaxed);
+        let paths: Vec<_> = files
+            .iter()
+            .map(|(extension, _)| format!("{n}.{extension}"))
+            .collect();
+
+        for (path, (_, contents)) in paths.iter().zip(files) {
+            self.sandbox.write(path, contents)?;
+        }
+
+        let result = f(&paths);
+
+        for path in paths.iter() {
+            self.sandbox.remove(path)?;
+        }
+
+        result
+    }
+}
+
+/// Interprets the exit status of a program following the testlib.h conventions. Partial scores
+/// are given by exiting with `_points`, where the message starts with a score between 0 and 1,
+/// after the word `points` as testlib's `quitp` writes it.
+pub(super) fn testlib_judgement(output: &Output) -> Result<Judgement, String> {
+    if output.timeout.is_some() {
+        return Err(String::from("judge program exceeded its time limit"));
+    } else if output.output_limit_exceeded {
+        return Err(String::from("judge program exceeded its output limit"));
+    }
+
+    let message = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+    let message = (!message.is_empty()).then_some(message);
+
+    match output.exit_status.code() {
+        Some(EXIT_OK) => Ok(Judgement::from(Verdict::Accepted).with_message(message)),
+        Some(EXIT_WRONG_ANSWER | EXIT_PRESENTATION_ERROR) => {
+            Ok(Judgement::from(Verdict::WrongAnswer).with_message(message))
+        }
+        Some(EXIT_POINTS) => {
+            let message = message.unwrap_or_default();
+            let message = match message.split_once(char::is_whitespace) {
+                Some(("points", rest)) => rest.trim_start(),
+                _ => &message,
+            };
+            let (score, message) = message
+                .split_once(char::is_whitespace)
+                .unwrap_or((message, ""));
+
+            let score = score
+                .parse::<f64>()
+                .ok()
+                .filter(|score| score.is_finite())
+                .ok_or_else(|| format!("judge program gave invalid score: {score}"))?;
+            let message = message.trim();
+
+            Ok(Judgement::partial(
+                score,
+                (!message.is_empty()).then(|| message.to_owned()),
+            ))
+        }
+        Some(EXIT_FAIL) => Err(message.unwrap_or_else(|| String::from("judge program failed"))),
+        code => Err(format!(
+            "judge program exited with unexpected status {}",
+            code.map_or_else(|| output.exit_status.to_string(), |c| c.to_string())
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
+
+    use time::Duration;
+
+    use super::*;
+
+    fn points(stderr: &str) -> Result<Judgement, String> {
+        testlib_judgement(&Output {
+            exit_status: ExitStatus::from_raw(EXIT_POINTS << 8),
+            stdout: Vec::new(),
+            stderr: stderr.as_bytes().to_vec(),
+            resource_usage: ResourceUsage {
+                user_time: Duration::ZERO,
+                sys_time: Duration::ZERO,
+                memory_bytes: 0,
+            },
+            timeout: None,
+            output_limit_exceeded: false,
+            memory_limit_exceeded: false,
+            forbidden_syscalls: Vec::new(),
+            forbidden_paths: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn partial_scores() {
+        let judgement = points("0.25 close").unwrap();
+        assert_eq!(judgement.verdict, Verdict::PartialScore);
+        assert_eq!(judgement.score, 0.25);
+        assert_eq!(judgement.message.as_deref(), Some("close"));
+
+        let judgement = points("points 0.5").unwrap();
+        assert_eq!(judgement.score, 0.5);
+        assert_eq!(judgement.message, None);
+
+        for score in ["nan", "NaN", "inf", "-inf", "abc"] {
+            assert_eq!(
+                points(score).unwrap_err(),
+                format!("judge program gave invalid score: {score}")
+            );
+        }
+    }
+}
@@ -0,0 +1,398 @@
+use std::{collections::BTreeMap, convert::Infallible, fmt, io, str::FromStr, sync::Arc};
+
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::*;
+use crate::contest::{ContestResourceLimits, Grader, Subtask, Task, TaskKind, Test};
+
+/// Messages from the web server to a remote worker. Every message is a line of JSON.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToWorker {
+    /// Judges a submission to the task with the given hash
+    Judge {
+        job: u64,
+        task: String,
+        input: JobInput,
+    },
+    /// The data of a task which the worker fetched
+    Task { hash: String, data: Box<TaskData> },
+}
+
+/// Messages from a remote worker to the web server.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToServer {
+    /// Sent once after connecting, with how many submissions the worker judges at once
+    Hello {
+        token: Option<Token>,
+        languages: Vec<String>,
+        capacity: usize,
+    },
+    /// Asks for the data of a task which the worker has not cached
+    Fetch {
+        hash: String,
+    },
+    Progress {
+        job: u64,
+        progress: Progress,
+    },
+    Done {
+        job: u64,
+        outcome: Outcome,
+    },
+}
+
+/// The shared secret workers give the web server, which is left out of logs.
+#[derive(Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Token(String);
+
+impl Token {
+    /// Compares tokens in time which does not depend on where they differ, by comparing their
+    /// hashes.
+    pub fn matches(&self, other: &Token) -> bool {
+        let (a, b) = (Sha256::digest(&self.0), Sha256::digest(&other.0));
+        a.iter()
+            .zip(b.iter())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Token(..)")
+    }
+}
+
+impl From<String> for Token {
+    fn from(token: String) -> Self {
+        Token(token)
+    }
+}
+
+impl FromStr for Token {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Token(s.to_owned()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobInput {
+    Code(Submission),
+    /// The uploaded files of an output-only submission
+    Outputs(Vec<(String, Vec<u8>)>),
+}
+
+impl JobInput {
+    pub fn judge(
+        self,
+        config: &Config,
+        task: &Task,
+        rlimits: ContestResourceLimits,
+        progress: &(dyn Fn(Progress) + Sync),
+    ) -> JudgeResult<Vec<TestResult>> {
+        match self {
+            JobInput::Code(submission) => run(config, submission, task, rlimits, progress),
+            JobInput::Outputs(files) => {
+                let limit = rlimits.run.output_bytes;
+                check_outputs(task, &collect_outputs(task, files, limit)?, limit)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Judged(Vec<TestResult>),
+    CompileError(String),
+    Failed { error: String, transient: bool },
+}
+
+/// Most bytes of a checker message or an error sent back to the server
+const MESSAGE_BYTES: usize = 64 << 10;
+
+/// Messages are truncated so that the outcome fits in `MAX_MESSAGE` however many tests there
+/// are, leaving room for the rest of each result and for escaping.
+impl From<JudgeResult<Vec<TestResult>>> for Outcome {
+    fn from(result: JudgeResult<Vec<TestResult>>) -> Self {
+        match result {
+            Ok(mut results) => {
+                let limit = (MAX_MESSAGE as usize / 8 / results.len().max(1)).min(MESSAGE_BYTES);
+                for message in results
+                    .iter_mut()
+                    .filter_map(|result| result.message.as_mut())
+                {
+                    truncate(message, limit);
+                }
+
+                Outcome::Judged(results)
+            }
+            Err(JudgeError::CompileError(mut stderr)) => {
+                truncate(&mut stderr, MESSAGE_BYTES);
+                Outcome::CompileError(stderr)
+            }
+            Err(e) => {
+                let mut error = e.to_string();
+                truncate(&mut error, MESSAGE_BYTES);
+                Outcome::Failed {
+                    error,
+                    transient: e.is_transient(),
+                }
+            }
+        }
+    }
+}
+
+/// Cuts a message down to at most `limit` bytes on a character boundary.
+fn truncate(message: &mut String, limit: usize) {
+    const MARKER: &str = "\n[truncated]";
+
+    if message.len() > limit {
+        let mut end = limit.saturating_sub(MARKER.len());
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        message.truncate(end);
+        message.push_str(MARKER);
+    }
+}
+
+impl From<Outcome> for JudgeResult<Vec<TestResult>> {
+    fn from(outcome: Outcome) -> Self {
+        match outcome {
+            Outcome::Judged(results) => Ok(results),
+            Outcome::CompileError(stderr) => Err(JudgeError::CompileError(stderr)),
+            Outcome::Failed { error, transient } => Err(JudgeError::Worker { error, transient }),
+        }
+    }
+}
+
+/// Everything a worker needs to judge submissions to a task. Checkers and interactors are sent
+/// as source and compiled by the worker with its own languages.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskData {
+    pub name: String,
+    pub rlimits: ContestResourceLimits,
+    pub kind: TaskKind,
+    pub short_circuit: bool,
+    pub comparison: Comparison,
+    pub tests: Vec<Test>,
+    pub subtasks: Vec<Subtask>,
+    pub checker: Option<ProgramSource>,
+    pub interactor: Option<ProgramSource>,
+    pub graders: BTreeMap<String, Grader>,
+}
+
+impl TaskData {
+    pub fn new(task: &Task, rlimits: ContestResourceLimits) -> Self {
+        TaskData {
+            name: task.name.clone(),
+            rlimits,
+            kind: task.kind,
+            short_circuit: task.short_circuit,
+            comparison: task.comparison,
+            tests: task.tests.clone(),
+            subtasks: task.subtasks.clone(),
+            checker: task
+                .checker
+                .as_ref()
+                .map(|checker| checker.source().clone()),
+            interactor: task
+                .interactor
+                .as_ref()
+                .map(|interactor| interactor.source().clone()),
+            graders: task
+                .graders
+                .iter()
+                .map(|(language, grader)| (language.clone(), grader.as_ref().clone()))
+                .collect(),
+        }
+    }
+
+    /// A hash of the contents, which workers cache tasks by.
+    pub fn hash(&self) -> String {
+        let json = serde_json::to_vec(self).expect("failed to serialize task");
+        format!("{:x}", Sha256::digest(json))
+    }
+
+    /// Compiles the checker or interactor, giving a task which can be judged locally.
+    pub fn compile(self, config: &Config) -> JudgeResult<(Task, ContestResourceLimits)> {
+        let checker = self
+            .checker
+            .map(|source| {
+                Checker::compile(
+                    config,
+                    config.language(&source.language)?,
+                    source.source,
+                    source.files,
+                    source.build_rlimits,
+                    source.rlimits,
+                )
+            })
+            .transpose()?;
+        let interactor = self
+            .interactor
+            .map(|source| {
+                Interactor::compile(
+                    config,
+                    config.language(&source.language)?,
+                    source.source,
+                    source.files,
+                    source.build_rlimits,
+                    source.rlimits,
+                )
+            })
+            .transpose()?;
+
+        let task = Task {
+            name: self.name,
+            path: None,
+            page: String::new(),
+            examples: Vec::new(),
+            subtasks: self.subtasks,
+            constraints: Vec::new(),
+            tests: self.tests,
+            difficulty: None,
+            kind: self.kind,
+            short_circuit: self.short_circuit,
+            comparison: self.comparison,
+            checker: checker.map(Arc::new),
+            interactor: interactor.map(Arc::new),
+            graders: self
+                .graders
+                .into_iter()
+                .map(|(language, grader)| (language, Arc::new(grader)))
+                .collect(),
+        };
+
+        Ok((task, self.rlimits))
+    }
+}
+
+/// Largest message the web server reads from a worker, which is far more than results need
+pub const MAX_MESSAGE: u64 = 16 << 20;
+
+/// Reads a message of at most `limit` bytes, or `None` once the connection is closed. A longer
+/// message is an error, after which the connection should be dropped.
+pub async fn read<T: DeserializeOwned>(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    limit: u64,
+) -> io::Result<Option<T>> {
+    let mut line = Vec::new();
+    let len = reader.take(limit).read_until(b'\n', &mut line).await?;
+
+    if len == 0 {
+        return Ok(None);
+    }
+    if len as u64 == limit && !line.ends_with(b"\n") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message is too long",
+        ));
+    }
+
+    serde_json::from_slice(&line)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub async fn write<T: Serialize>(
+    writer: &mut (impl AsyncWrite + Unpin),
+    message: &T,
+) -> io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    writer.write_all(&line).await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::BufReader;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn messages() {
+        let (client, server) = tokio::io::duplex(1024);
+        let (mut client, mut server) = (client, BufReader::new(server));
+
+        let outcome: JudgeResult<Vec<TestResult>> =
+            Err(JudgeError::CompileError(String::from("error\nmore")));
+        for message in [
+            ToServer::Fetch {
+                hash: String::from("abc"),
+            },
+            ToServer::Done {
+                job: 1,
+                outcome: outcome.into(),
+            },
+        ] {
+            write(&mut client, &message).await.unwrap();
+        }
+        drop(client);
+
+        assert!(matches!(
+            read(&mut server, MAX_MESSAGE).await.unwrap(),
+            Some(ToServer::Fetch { hash }) if hash == "abc"
+        ));
+        assert!(matches!(
+            read(&mut server, MAX_MESSAGE).await.unwrap(),
+            Some(ToServer::Done { job: 1, outcome: Outcome::CompileError(stderr) }) if stderr == "error\nmore"
+        ));
+        assert!(read::<ToServer>(&mut server, MAX_MESSAGE)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn tokens() {
+        let token = Token::from(String::from("secret"));
+        assert!(token.matches(&Token::from(String::from("secret"))));
+        assert!(!token.matches(&Token::from(String::from("secreT"))));
+        assert!(!format!("{token:?}").contains("secret"));
+    }
+
+    #[test]
+    fn outcomes_fit_in_messages() {
+        let result = TestResult {
+            verdict: Verdict::WrongAnswer,
+            score: 0.0,
+            message: Some("é".repeat(MESSAGE_BYTES)),
+            resource_usage: None,
+            exit_code: None,
+            signal: None,
+        };
+        let outcome = Outcome::from(Ok(vec![result; 1000]));
+        let json = serde_json::to_vec(&outcome).unwrap();
+        assert!((json.len() as u64) < MAX_MESSAGE);
+
+        let Outcome::Judged(results) = outcome else {
+            panic!("expected results, got {outcome:?}");
+        };
+        let message = results[0].message.as_deref().unwrap();
+        assert!(message.len() <= MAX_MESSAGE as usize / 8 / 1000);
+        assert!(message.ends_with("[truncated]"));
+
+        let stderr = String::from("error\n").repeat(MESSAGE_BYTES);
+        let outcome = Outcome::from(Err(JudgeError::CompileError(stderr)));
+        assert!(matches!(outcome, Outcome::CompileError(stderr) if stderr.len() <= MESSAGE_BYTES));
+    }
+
+    #[tokio::test]
+    async fn long_messages() {
+        let mut reader: &[u8] = b"{\"fetch\":{\"hash\":\"abcdef\"}}\n";
+
+        let error = read::<ToServer>(&mut reader, 16).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,55 +1,133 @@
+use std::{os::unix::process::ExitStatusExt, sync::Mutex};
+
 use rayon::prelude::*;
+use serde::Serialize;
 
 use super::*;
-use crate::contest::{ContestResourceLimits, Task, Test};
+use crate::contest::{ContestResourceLimits, Grader, Task, Test};
 
 const MEMORY_USAGE_EPSILON: u64 = 1000;
-const TIME_ELAPSED_EPSILON: f64 = 0.1;
+const WALL_TIME_EXCEEDED: &str = "wall-clock time limit exceeded";
+const FORBIDDEN_PATH: &str = "tried to open the test data";
+/// What the `grader` command of a language compiles a grader into
+const GRADER_OBJECT: &str = "grader.o";
+/// Compiler diagnostics beyond this are cut from the compile error shown to the contestant
+const COMPILE_ERROR_BYTES: usize = 64 << 10;
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TestResult {
     pub verdict: Verdict,
+    pub score: f64,
+    pub message: Option<String>,
     pub resource_usage: Option<ResourceUsage>,
+    /// The non-zero exit code of a crashed process
+    pub exit_code: Option<i32>,
+    /// The signal a crashed process was killed by
+    pub signal: Option<i32>,
+}
+
+/// Progress reported while a submission is judged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Progress {
+    Compiling,
+    Running {
+        completed: usize,
+        total: usize,
+    },
+    /// A subtask has been judged, numbered from 1
+    Subtask {
+        subtask: usize,
+        verdict: Verdict,
+        score: u32,
+    },
+}
+
+impl TestResult {
+    fn skipped() -> Self {
+        TestResult {
+            verdict: Verdict::Skipped,
+            score: 0.0,
+            message: None,
+            resource_usage: None,
+            exit_code: None,
+            signal: None,
+        }
+    }
 }
 
-#[tracing::instrument(skip(task), err)]
+#[tracing::instrument(skip(task, progress), err)]
 pub fn run(
     config: &Config,
     submission: Submission,
     task: &Task,
     rlimits: ContestResourceLimits,
+    progress: &(dyn Fn(Progress) + Sync),
 ) -> JudgeResult<Vec<TestResult>> {
-    let Some(language) = config
-        .languages
-        .iter()
-        .find(|language| language.name == submission.language)
-    else {
-        return Err(JudgeError::UnknownLanguage(submission.language));
-    };
+    let language = config.language(&submission.language)?;
+
+    let grader = task.grader(language)?;
 
-    let sandbox = Sandbox::new()?;
+    let mut sandbox = Sandbox::for_submission(config, language)?;
+    if let Some(path) = &task.path {
+        sandbox.protect(path)?;
+    }
     sandbox.write(&language.filename, submission.code)?;
 
+    let mut sources = Vec::new();
+
     if let Some(command) = &language.build {
-        build(&sandbox, command, rlimits.build)?;
+        progress(Progress::Compiling);
+
+        // The grader is compiled on its own, so that the submission cannot include its sources
+        if let Some(grader) = grader {
+            let object = build_grader(config, language, grader, rlimits.build)?;
+            sandbox.write(GRADER_OBJECT, object)?;
+            for (path, contents) in grader.headers() {
+                sandbox.write(path, contents)?;
+            }
+
+            sources.push(String::from(GRADER_OBJECT));
+        }
+
+        sources.push(language.filename.clone());
+        build(&sandbox, &command.with_sources(&sources), rlimits.build)?;
+
+        // The grader is linked into the executable, so the submission cannot read it either
+        if let Some(grader) = grader {
+            sandbox.remove(GRADER_OBJECT)?;
+            for (path, _) in grader.headers() {
+                sandbox.remove(path)?;
+            }
+        }
     } else {
+        sources.push(language.filename.clone());
         tracing::debug!("skipping build (no build step)");
     }
 
-    let mut verdicts: Vec<_> = task
-        .tests
-        .par_iter()
-        .enumerate()
-        .map(|(idx, test_case)| {
-            let verdict = test(
-                &sandbox,
-                &language.run,
-                rlimits.run,
-                test_case,
-                (idx + 1, task.tests.len()),
-            )?;
-            Ok((idx, verdict))
-        })
+    let command = language.run.with_sources(&sources);
+    let tracker = Tracker::new(task, progress);
+
+    let run_test = |idx: usize| {
+        let result = test(
+            &sandbox,
+            &command,
+            rlimits.run,
+            &task.tests[idx],
+            task,
+            (idx + 1, task.tests.len()),
+        )?;
+        tracker.judged(idx, &result);
+
+        Ok(result)
+    };
+
+    if task.short_circuit {
+        return run_short_circuit(task, &tracker, rayon::current_num_threads(), run_test);
+    }
+
+    let mut verdicts: Vec<_> = (0..task.tests.len())
+        .into_par_iter()
+        .map(|idx| Ok((idx, run_test(idx)?)))
         .collect::<JudgeResult<_>>()?;
 
     verdicts.par_sort_by_key(|(idx, _)| *idx);
@@ -59,15 +137,194 @@ pub fn run(
         .collect())
 }
 
+/// Judges tests in parallel batches, in the order of the subtasks containing them, skipping the
+/// rest of a subtask once a test has decided that the subtask is failed. Tests are only skipped
+/// if every subtask containing them has failed.
+fn run_short_circuit(
+    task: &Task,
+    tracker: &Tracker,
+    batch_size: usize,
+    run_test: impl Fn(usize) -> JudgeResult<TestResult> + Sync,
+) -> JudgeResult<Vec<TestResult>> {
+    let mut results: Vec<Option<TestResult>> = vec![None; task.tests.len()];
+    let mut failed = vec![false; task.subtasks.len()];
+
+    let mut order = Vec::with_capacity(task.tests.len());
+    for subtask in task.subtasks.iter() {
+        for &test in subtask.tests.iter() {
+            if !order.contains(&test) {
+                order.push(test);
+            }
+        }
+    }
+
+    let needed = |failed: &[bool], test: usize| {
+        task.subtasks
+            .iter()
+            .zip(failed)
+            .any(|(subtask, &failed)| !failed && subtask.tests.contains(&test))
+    };
+
+    let mut skipped = vec![false; task.tests.len()];
+
+    loop {
+        // Reported once skipped, so that subtasks sharing the test can be reported too
+        for test in 0..task.tests.len() {
+            if results[test].is_none() && !skipped[test] && !needed(&failed, test) {
+                skipped[test] = true;
+                tracker.skipped(test);
+            }
+        }
+
+        let batch: Vec<_> = order
+            .iter()
+            .copied()
+            .filter(|&test| results[test].is_none() && needed(&failed, test))
+            .take(batch_size)
+            .collect();
+
+        if batch.is_empty() {
+            break;
+        }
+
+        let judged: Vec<_> = batch
+            .into_par_iter()
+            .map(|test| Ok((test, run_test(test)?)))
+            .collect::<JudgeResult<_>>()?;
+
+        for (test, result) in judged {
+            for (idx, subtask) in task.subtasks.iter().enumerate() {
+                if subtask.tests.contains(&test) && subtask.scoring.decided_by(result.score) {
+                    if !failed[idx] {
+                        tracing::trace!("skipping remaining tests of subtask {}", idx + 1);
+                    }
+
+                    failed[idx] = true;
+                }
+            }
+
+            results[test] = Some(result);
+        }
+    }
+
+    Ok(results
+        .into_iter()
+        .map(|result| result.unwrap_or_else(TestResult::skipped))
+        .collect())
+}
+
+/// Reports tests as they are judged or skipped, and subtasks once all of their tests are.
+struct Tracker<'a> {
+    task: &'a Task,
+    progress: &'a (dyn Fn(Progress) + Sync),
+    state: Mutex<TrackerState>,
+}
+
+struct TrackerState {
+    results: Vec<Option<TestResult>>,
+    reported: Vec<bool>,
+    completed: usize,
+}
+
+impl<'a> Tracker<'a> {
+    fn new(task: &'a Task, progress: &'a (dyn Fn(Progress) + Sync)) -> Self {
+        progress(Progress::Running {
+            completed: 0,
+            total: task.tests.len(),
+        });
+
+        Tracker {
+            task,
+            progress,
+            state: Mutex::new(TrackerState {
+                results: vec![None; task.tests.len()],
+                reported: vec![false; task.subtasks.len()],
+                completed: 0,
+            }),
+        }
+    }
+
+    fn judged(&self, test: usize, result: &TestResult) {
+        let mut state = self.state.lock().expect("failed to lock progress");
+        state.results[test] = Some(result.clone());
+        state.completed += 1;
+
+        (self.progress)(Progress::Running {
+            completed: state.completed,
+            total: self.task.tests.len(),
+        });
+
+        for (idx, subtask) in self.task.subtasks.iter().enumerate() {
+            if subtask
+                .tests
+                .iter()
+                .all(|&test| state.results[test].is_some())
+            {
+                self.report(&mut state, idx);
+            }
+        }
+    }
+
+    /// Reports a test which is skipped, as every subtask containing it has failed.
+    fn skipped(&self, test: usize) {
+        self.judged(test, &TestResult::skipped());
+    }
+
+    fn report(&self, state: &mut TrackerState, idx: usize) {
+        if state.reported[idx] {
+            return;
+        }
+
+        state.reported[idx] = true;
+
+        let results: Vec<_> = state
+            .results
+            .iter()
+            .map(|result| result.clone().unwrap_or_else(TestResult::skipped))
+            .collect();
+        let grade = grade_subtask(&self.task.subtasks[idx], &results);
+
+        (self.progress)(Progress::Subtask {
+            subtask: idx + 1,
+            verdict: grade.verdict,
+            score: grade.score,
+        });
+    }
+}
+
 #[tracing::instrument(err)]
-fn build(sandbox: &Sandbox, command: &Command, rlimits: ResourceLimits) -> JudgeResult<()> {
+pub(super) fn build(
+    sandbox: &Sandbox,
+    command: &Command,
+    rlimits: ResourceLimits,
+) -> JudgeResult<()> {
     tracing::debug!("starting build");
     let output = sandbox.build(command, rlimits)?;
 
-    if !output.exit_status.success() {
-        tracing::error!("build failed");
+    let limit = if output.timeout == Some(Timeout::WallTime) {
+        Some(format!(
+            "wall-clock time limit of {}s",
+            rlimits.wall_time().as_seconds_f64()
+        ))
+    } else if output.timeout == Some(Timeout::CpuTime)
+        || output.resource_usage.total_time() > rlimits.cpu_time
+    {
+        Some(format!(
+            "time limit of {}s",
+            rlimits.cpu_time.as_seconds_f64()
+        ))
+    } else if output.memory_limit_exceeded {
+        Some(String::from("memory limit"))
+    } else if output.output_limit_exceeded {
+        Some(String::from("output limit"))
+    } else if output.exit_status.signal() == Some(libc::SIGXFSZ) {
+        Some(String::from("file size limit"))
+    } else {
+        None
+    };
 
-        let stderr = std::str::from_utf8(&output.stderr).unwrap_or_default();
+    if limit.is_some() || !output.exit_status.success() {
+        tracing::error!("build failed");
 
         if let Ok(stdout) = std::str::from_utf8(&output.stdout) {
             if !stdout.is_empty() {
@@ -75,7 +332,14 @@ fn build(sandbox: &Sandbox, command: &Command, rlimits: ResourceLimits) -> Judge
             }
         }
 
-        Err(JudgeError::CompileError(stderr.to_owned()))
+        let mut message = compile_error(&output.stderr);
+        if let Some(limit) = limit {
+            message = format!("Compilation exceeded the {limit}\n\n{message}")
+                .trim_end()
+                .to_owned();
+        }
+
+        Err(JudgeError::CompileError(message))
     } else {
         let duration = output.resource_usage.user_time + output.resource_usage.user_time;
         tracing::debug!("build completed in {:.03}", duration.as_seconds_f64());
@@ -83,50 +347,341 @@ fn build(sandbox: &Sandbox, command: &Command, rlimits: ResourceLimits) -> Judge
     }
 }
 
-#[tracing::instrument(skip(sandbox, command, rlimits, test, test_count), err)]
+/// Compiles a grader in a sandbox of its own, giving the object which submissions are built with.
+fn build_grader(
+    config: &Config,
+    language: &Language,
+    grader: &Grader,
+    rlimits: ResourceLimits,
+) -> JudgeResult<Vec<u8>> {
+    let command = language
+        .grader
+        .as_ref()
+        .ok_or_else(|| JudgeError::UnsupportedLanguage(language.name.clone()))?;
+
+    let sandbox = Sandbox::for_judge(config, language)?;
+    for (path, contents) in grader.files.iter() {
+        sandbox.write(path, contents)?;
+    }
+
+    // The contestant is not shown the diagnostics, which are about the task's own code
+    build(&sandbox, &command.with_sources(&grader.sources), rlimits).map_err(|e| match e {
+        JudgeError::CompileError(stderr) => JudgeError::Grader(stderr),
+        e => e,
+    })?;
+
+    Ok(sandbox.read(GRADER_OBJECT)?)
+}
+
+/// The diagnostics of a failed build, cut to a length that can be stored and shown.
+fn compile_error(stderr: &[u8]) -> String {
+    if stderr.len() > COMPILE_ERROR_BYTES {
+        let stderr = String::from_utf8_lossy(&stderr[..COMPILE_ERROR_BYTES]);
+        format!("{stderr}\n[diagnostics truncated]")
+    } else {
+        String::from_utf8_lossy(stderr).into_owned()
+    }
+}
+
+#[tracing::instrument(skip(sandbox, command, rlimits, test, task, test_count), err)]
 fn test(
     sandbox: &Sandbox,
     command: &Command,
     rlimits: ResourceLimits,
     test: &Test,
+    task: &Task,
     (test_number, test_count): (usize, usize),
 ) -> JudgeResult<TestResult> {
-    let output = match sandbox.run(command, test.input.as_bytes(), rlimits) {
-        Ok(output) => output,
-        Err(e) => return Err(JudgeError::Io(e)),
+    let (output, interaction) = match &task.interactor {
+        Some(interactor) => {
+            let (output, judgement) =
+                interactor.interact(sandbox, command, rlimits, &test.input, &test.output)?;
+            (output, Some(judgement))
+        }
+        None => (sandbox.run(command, test.input.as_bytes(), rlimits)?, None),
     };
 
-    let stdout = std::str::from_utf8(&output.stdout)?;
+    // The submission may be killed by SIGPIPE if the interactor exits first
+    let interaction_finished = interaction.is_some()
+        && (output.exit_status.success() || output.exit_status.signal() == Some(libc::SIGPIPE));
 
-    let verdict = if output.exit_status.success() {
-        if stdout.trim() == test.output.trim() {
-            Verdict::Accepted
-        } else {
-            Verdict::WrongAnswer
-        }
+    let judgement = if !output.forbidden_syscalls.is_empty() {
+        Judgement::from(Verdict::SecurityViolation)
+            .with_message(Some(forbidden_syscalls(&output.forbidden_syscalls)))
+    } else if !output.forbidden_paths.is_empty() {
+        Judgement::from(Verdict::SecurityViolation).with_message(Some(String::from(FORBIDDEN_PATH)))
+    } else if output.output_limit_exceeded {
+        Verdict::OutputLimitExceeded.into()
+    } else if output.memory_limit_exceeded {
+        Verdict::MemoryLimitExceeded.into()
+    } else if output.timeout == Some(Timeout::WallTime) {
+        Judgement::from(Verdict::TimeLimitExceeded)
+            .with_message(Some(String::from(WALL_TIME_EXCEEDED)))
+    } else if output.timeout == Some(Timeout::CpuTime)
+        || output.resource_usage.total_time() > rlimits.cpu_time
+    {
+        Verdict::TimeLimitExceeded.into()
+    } else if interaction_finished {
+        interaction.expect("no interaction")
+    } else if output.exit_status.success() {
+        judge_output(task, test, &output.stdout)?
+    } else if output.exit_status.signal() == Some(libc::SIGXFSZ) {
+        Verdict::OutputLimitExceeded.into()
     } else if output.exit_status.code().is_none() {
         let (memory_usage, memory_limit) =
             (output.resource_usage.memory_bytes, rlimits.memory_bytes);
 
-        let (time_elapsed, time_limit) = (
-            output.resource_usage.total_time().as_seconds_f64(),
-            rlimits.cpu_seconds as f64,
-        );
-
         if memory_usage > memory_limit || memory_limit - memory_usage <= MEMORY_USAGE_EPSILON {
-            Verdict::MemoryLimitExceeded
-        } else if time_elapsed > time_limit || time_limit - time_elapsed <= TIME_ELAPSED_EPSILON {
-            Verdict::TimeLimitExceeded
+            Verdict::MemoryLimitExceeded.into()
         } else {
-            panic!("process was killed by signal but it did not exceed the time or memory limit");
+            Verdict::RuntimeError.into()
         }
     } else {
-        Verdict::RuntimeError
+        Verdict::RuntimeError.into()
+    };
+
+    let Judgement {
+        verdict,
+        score,
+        message,
+    } = judgement;
+
+    // Record why the process crashed, so that it can be shown to the contestant
+    let (exit_code, signal) = if verdict == Verdict::RuntimeError {
+        (
+            output.exit_status.code().filter(|&code| code != 0),
+            output.exit_status.signal(),
+        )
+    } else {
+        (None, None)
     };
 
     tracing::trace!("[{test_number}/{test_count}] {}", verdict.fmt_colored());
     Ok(TestResult {
         verdict,
+        score,
+        message,
         resource_usage: Some(output.resource_usage),
+        exit_code,
+        signal,
     })
 }
+
+fn forbidden_syscalls(syscalls: &[i64]) -> String {
+    let syscalls: Vec<_> = syscalls
+        .iter()
+        .map(|&nr| match syscall_name(nr) {
+            Some(name) => format!("{name} ({nr})"),
+            None => nr.to_string(),
+        })
+        .collect();
+
+    format!("forbidden system call: {}", syscalls.join(", "))
+}
+
+/// Judges an output against the reference answers of a test, with the task checker if it has one.
+pub(super) fn judge_output(task: &Task, test: &Test, output: &[u8]) -> JudgeResult<Judgement> {
+    if let Some(checker) = &task.checker {
+        checker.check(&test.input, output, &test.output)
+    } else {
+        let Ok(output) = std::str::from_utf8(output) else {
+            return Ok(Judgement::from(Verdict::WrongAnswer)
+                .with_message(Some(String::from("output is not valid UTF-8"))));
+        };
+
+        if test
+            .outputs()
+            .any(|answer| task.comparison.matches(output, answer))
+        {
+            Ok(Verdict::Accepted.into())
+        } else {
+            Ok(Verdict::WrongAnswer.into())
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{collections::HashMap, fs, sync::Arc};
+
+    use super::*;
+    use crate::contest::{Subtask, TaskKind};
+
+    pub(crate) fn task(subtasks: &[&[usize]], tests: usize) -> Task {
+        Task {
+            name: String::from("task"),
+            path: None,
+            page: String::new(),
+            examples: Vec::new(),
+            subtasks: subtasks
+                .iter()
+                .map(|tests| Subtask {
+                    tests: tests.to_vec(),
+                    includes: Vec::new(),
+                    points: 50,
+                    scoring: Scoring::Minimum,
+                    constraints: Vec::new(),
+                })
+                .collect(),
+            constraints: Vec::new(),
+            tests: (0..tests)
+                .map(|idx| Test {
+                    name: idx.to_string(),
+                    input: String::new(),
+                    output: String::new(),
+                    alternatives: Vec::new(),
+                })
+                .collect(),
+            difficulty: None,
+            kind: TaskKind::default(),
+            short_circuit: true,
+            comparison: Comparison::default(),
+            checker: None,
+            interactor: None,
+            graders: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn short_circuit() {
+        // Test 1 fails the first subtask, but test 3 is still needed by the second, and its
+        // verdict is the one given to both
+        let task = task(&[&[0, 1, 2, 3], &[0, 3]], 4);
+        let verdicts = [
+            Verdict::Accepted,
+            Verdict::WrongAnswer,
+            Verdict::Accepted,
+            Verdict::SecurityViolation,
+        ];
+
+        let progress = Mutex::new(Vec::new());
+        let report = |update| progress.lock().unwrap().push(update);
+        let tracker = Tracker::new(&task, &report);
+        let ran = Mutex::new(Vec::new());
+
+        let results = run_short_circuit(&task, &tracker, 1, |test| {
+            ran.lock().unwrap().push(test);
+            let Judgement { verdict, score, .. } = verdicts[test].into();
+            let result = TestResult {
+                verdict,
+                score,
+                ..TestResult::skipped()
+            };
+            tracker.judged(test, &result);
+            Ok(result)
+        })
+        .unwrap();
+
+        assert_eq!(*ran.lock().unwrap(), [0, 1, 3]);
+        assert_eq!(
+            results
+                .iter()
+                .map(|result| result.verdict)
+                .collect::<Vec<_>>(),
+            [
+                Verdict::Accepted,
+                Verdict::WrongAnswer,
+                Verdict::Skipped,
+                Verdict::SecurityViolation
+            ]
+        );
+
+        // Skipped tests count as completed, and subtasks are reported as they are graded
+        let progress = progress.into_inner().unwrap();
+        assert_eq!(
+            progress
+                .iter()
+                .rfind(|update| matches!(update, Progress::Running { .. })),
+            Some(&Progress::Running {
+                completed: 4,
+                total: 4
+            })
+        );
+
+        for (idx, subtask) in task.subtasks.iter().enumerate() {
+            let grade = grade_subtask(subtask, &results);
+            let reports: Vec<_> = progress
+                .iter()
+                .filter(|update| {
+                    matches!(update, Progress::Subtask { subtask, .. } if *subtask == idx + 1)
+                })
+                .collect();
+            assert_eq!(
+                reports,
+                [&Progress::Subtask {
+                    subtask: idx + 1,
+                    verdict: grade.verdict,
+                    score: grade.score
+                }]
+            );
+        }
+    }
+
+    #[test]
+    #[ignore = "needs Landlock"]
+    fn reading_test_data_is_a_violation() {
+        let config: Config = toml::from_str(
+            "[[language]]\nname = \"sh\"\nfilename = \"submission.sh\"\nrun = \"/bin/sh submission.sh\"",
+        )
+        .unwrap();
+
+        // The submission prints the answer from the task directory instead of solving the test
+        let dir = tempfile::tempdir().unwrap();
+        let answer = dir.path().join("tests").join("0.out");
+        fs::create_dir(dir.path().join("tests")).unwrap();
+        fs::write(&answer, "42").unwrap();
+
+        let mut task = task(&[&[0]], 1);
+        task.path = Some(dir.path().to_owned());
+        task.tests[0].output = String::from("42");
+
+        let submission = Submission {
+            code: format!("exec cat {}", answer.display()),
+            language: String::from("sh"),
+        };
+        let rlimits: ContestResourceLimits = toml::from_str(
+            "build = { time = 1, memory = \"256MiB\" }\nrun = { time = 1, memory = \"256MiB\" }",
+        )
+        .unwrap();
+        let results = run(&config, submission, &task, rlimits, &|_| {}).unwrap();
+
+        assert_eq!(results[0].verdict, Verdict::SecurityViolation);
+        assert_eq!(results[0].message.as_deref(), Some(FORBIDDEN_PATH));
+    }
+
+    #[test]
+    fn graders_are_built_apart() {
+        // "Compiling" copies the grader into its object, and the submission builds itself
+        let config: Config = toml::from_str(
+            "[[language]]\nname = \"sh\"\nfilename = \"submission.sh\"\ngrader = \"/bin/cp {sources} grader.o\"\nbuild = \"/bin/sh submission.sh {sources}\"\nrun = \"/bin/sh program\"",
+        )
+        .unwrap();
+
+        let mut task = task(&[&[0]], 1);
+        task.tests[0].output = String::from("42");
+        task.graders.insert(
+            String::from("sh"),
+            Arc::new(Grader {
+                sources: vec![String::from("grader.sh")],
+                files: vec![
+                    (String::from("grader.sh"), b"echo 42".to_vec()),
+                    (String::from("grader.h"), Vec::new()),
+                ],
+            }),
+        );
+
+        let submission = Submission {
+            code: String::from(
+                "if [ -e grader.sh ] || [ ! -e grader.h ]; then echo leaked > program; else cat \"$1\" > program; fi",
+            ),
+            language: String::from("sh"),
+        };
+        let rlimits: ContestResourceLimits = toml::from_str(
+            "build = { time = 1, memory = \"256MiB\" }\nrun = { time = 1, memory = \"256MiB\" }",
+        )
+        .unwrap();
+        let results = run(&config, submission, &task, rlimits, &|_| {}).unwrap();
+
+        assert_eq!(results[0].verdict, Verdict::Accepted);
+    }
+}
@@ -1,26 +1,65 @@
 use std::{
     ffi::{OsStr, OsString},
-    fs,
+    fs::{self, File},
     io::{self, Read, Write},
-    os::unix::process::CommandExt,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::{self, fs::PermissionsExt, process::CommandExt},
+    },
     path::{Path, PathBuf},
     process::{self, ExitStatus, Stdio},
     str::FromStr,
+    sync::Arc,
+    thread,
 };
 
-pub use resource::{ResourceLimits, ResourceUsage};
+use cgroup::Cgroup;
+use landlock::Ruleset;
+use namespace::Namespaces;
+pub use resource::{ResourceLimits, ResourceUsage, StackLimit};
+pub use seccomp::{
+    syscalls::name as syscall_name, InvalidAllowlist, SeccompMode, SyscallAllowlist,
+};
+use seccomp::{Notifier, OpenCheck};
+use seccompiler::BpfProgram;
+use serde::Deserialize;
 use serde_with::DeserializeFromStr;
 use tempfile::TempDir;
 use thiserror::Error;
+pub use users::UserPool;
+use users::{Lease, User};
+pub use watchdog::Timeout;
+use watchdog::Watchdog;
 
+pub(super) mod cgroup;
+pub(super) mod landlock;
+pub(super) mod namespace;
 mod resource;
 mod seccomp;
+mod users;
+mod watchdog;
 
 #[derive(Debug)]
 pub struct Sandbox {
     dir: TempDir,
+    /// Owns the sandbox directory and runs its commands if set
+    user: Option<User>,
+    lease: Option<Lease>,
+    /// Runs are placed in a child cgroup of this cgroup if set
+    cgroup_root: Option<PathBuf>,
+    /// Restricts the filesystem access of runs if Landlock is supported
+    landlock: Option<Ruleset>,
+    /// Isolates commands of the enabled profiles in namespaces
+    namespaces: Option<Arc<Namespaces>>,
+    seccomp: SeccompMode,
+    /// The system calls runs may make, which depend on the language
+    allowlist: SyscallAllowlist,
+    /// Directories which runs are judged for trying to read
+    protected: Vec<PathBuf>,
 }
 
+const SOURCES_PLACEHOLDER: &str = "{sources}";
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq, DeserializeFromStr)]
 pub struct Command {
     executable: PathBuf,
@@ -37,6 +76,36 @@ impl Command {
             args: args.into_iter().map(|s| s.as_ref().to_owned()).collect(),
         }
     }
+
+    pub fn with_args(&self, args: impl IntoIterator<Item = impl AsRef<OsStr>>) -> Self {
+        let mut command = self.clone();
+        command
+            .args
+            .extend(args.into_iter().map(|s| s.as_ref().to_owned()));
+        command
+    }
+
+    /// Replaces a `{sources}` argument with the given source files, e.g. `g++ {sources} -o
+    /// submission` for a submission compiled together with a grader.
+    pub fn with_sources(&self, sources: &[String]) -> Self {
+        let mut command = self.clone();
+        command.args = self
+            .args
+            .iter()
+            .flat_map(|arg| {
+                if arg == SOURCES_PLACEHOLDER {
+                    sources.iter().map(OsString::from).collect()
+                } else {
+                    vec![arg.clone()]
+                }
+            })
+            .collect();
+        command
+    }
+
+    pub fn takes_sources(&self) -> bool {
+        self.args.iter().any(|arg| arg == SOURCES_PLACEHOLDER)
+    }
 }
 
 #[derive(Debug, Error)]
@@ -66,17 +135,120 @@ pub struct Output {
     pub stdout: Vec<u8>,
     pub stderr: Vec<u8>,
     pub resource_usage: ResourceUsage,
+    /// Set if the judge killed the process for exceeding a time limit
+    pub timeout: Option<Timeout>,
+    /// Whether the process was killed for writing more than the output limit
+    pub output_limit_exceeded: bool,
+    /// Whether the process was killed for exceeding the memory limit, which is only known when
+    /// running in a cgroup
+    pub memory_limit_exceeded: bool,
+    /// The system calls the process was denied, which are only reported if seccomp is set to
+    /// trap or kill
+    pub forbidden_syscalls: Vec<i64>,
+    /// The files in protected directories the process tried to open
+    pub forbidden_paths: Vec<PathBuf>,
 }
 
 impl Sandbox {
     pub fn new() -> io::Result<Self> {
         let dir = tempfile::tempdir()?;
-        Ok(Sandbox { dir })
+        Ok(Sandbox {
+            dir,
+            user: None,
+            lease: None,
+            cgroup_root: None,
+            landlock: None,
+            namespaces: None,
+            seccomp: SeccompMode::default(),
+            allowlist: SyscallAllowlist::default(),
+            protected: Vec::new(),
+        })
+    }
+
+    /// Creates a sandbox for a submission, owned by a user leased from the pool if there is one.
+    pub fn for_submission(config: &super::Config, language: &super::Language) -> io::Result<Self> {
+        let lease = config.users.map(|users| users.lease());
+        let user = lease.as_ref().map(Lease::user);
+        let mut sandbox = Sandbox::new()?.with_config(config, language, user)?;
+        sandbox.lease = lease;
+        Ok(sandbox)
+    }
+
+    /// Creates a sandbox for task-supplied judge programs, owned by the judge user of the pool if
+    /// there is one.
+    pub fn for_judge(config: &super::Config, language: &super::Language) -> io::Result<Self> {
+        let user = config.users.map(|users| users.judge());
+        Sandbox::new()?.with_config(config, language, user)
+    }
+
+    fn with_config(
+        mut self,
+        config: &super::Config,
+        language: &super::Language,
+        user: Option<User>,
+    ) -> io::Result<Self> {
+        // The judge keeps the directory, so that it can write and remove files in it without
+        // overriding permissions, and only shares it with the group of the user
+        if let Some(user) = user {
+            unix::fs::chown(self.path(), None, Some(user.gid))?;
+            fs::set_permissions(self.path(), fs::Permissions::from_mode(0o770))?;
+            self.user = Some(user);
+        }
+
+        self.cgroup_root = config.cgroup.clone();
+        self.seccomp = config.seccomp;
+        self.allowlist = language.seccomp.clone();
+        self.landlock = Ruleset::new(self.path(), &config.readable_paths)?;
+
+        if !config.namespaces.is_empty() {
+            let namespaces = Namespaces::new(
+                self.path(),
+                &config.readable_paths,
+                config.namespaces.clone(),
+                user,
+            )?;
+            self.namespaces = Some(Arc::new(namespaces));
+        }
+
+        Ok(self)
     }
 
     pub fn write(&self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
         tracing::trace!("writing code to {}", path.as_ref().display());
-        fs::write(self.path().join(path), contents)
+        let path = self.path().join(path);
+        fs::write(&path, contents)?;
+
+        match self.user {
+            Some(user) => unix::fs::chown(path, Some(user.uid), Some(user.gid)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn read(&self, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        fs::read(self.path().join(path))
+    }
+
+    pub fn remove(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::remove_file(self.path().join(path))
+    }
+
+    /// Judges runs which try to open files in a directory, such as the test data of a task, as
+    /// a security violation rather than just failing them. Fails unless Landlock or namespaces
+    /// keep runs from opening them at all, as checking the opens alone is racy.
+    pub fn protect(&mut self, dir: impl AsRef<Path>) -> io::Result<()> {
+        let namespaced = self
+            .namespaces
+            .as_ref()
+            .is_some_and(|namespaces| namespaces.enabled(Profile::Run));
+        if self.landlock.is_none() && !namespaced {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "protecting test data needs Landlock or namespaces for runs",
+            ));
+        }
+
+        self.protected.push(dir.as_ref().canonicalize()?);
+        Ok(())
     }
 
     pub fn build(&self, command: &Command, rlimits: ResourceLimits) -> io::Result<Output> {
@@ -92,77 +264,609 @@ impl Sandbox {
         self.exec(command, Some(stdin), rlimits, Profile::Run)
     }
 
+    /// Runs a submission with its stdin and stdout cross-connected to an interactor running in
+    /// another sandbox, returning the output of the submission and the interactor.
+    #[tracing::instrument(skip(interactor), err)]
+    pub fn interact(
+        &self,
+        command: &Command,
+        rlimits: ResourceLimits,
+        interactor: &Sandbox,
+        interactor_command: &Command,
+        interactor_rlimits: ResourceLimits,
+    ) -> io::Result<(Output, Output)> {
+        let (submission_stdin, interactor_stdout) = pipe()?;
+        let (interactor_stdin, submission_stdout) = pipe()?;
+
+        let cgroup = self.cgroup(rlimits, Profile::Run)?;
+
+        let (mut submission, submission_pids, notifier) = self.spawn(
+            command,
+            rlimits,
+            Profile::Run,
+            cgroup.as_ref(),
+            [
+                submission_stdin.into(),
+                submission_stdout.into(),
+                Stdio::piped(),
+            ],
+        )?;
+
+        let interactor = interactor.spawn(
+            interactor_command,
+            interactor_rlimits,
+            Profile::Judge,
+            None,
+            [
+                interactor_stdin.into(),
+                interactor_stdout.into(),
+                Stdio::piped(),
+            ],
+        );
+
+        let (mut interactor, interactor_pids, _) = match interactor {
+            Ok(interactor) => interactor,
+            Err(e) => {
+                watchdog::kill_group(submission_pids.group);
+                resource::wait4(submission_pids.group).ok();
+                return Err(e);
+            }
+        };
+
+        let watchdog = Watchdog::start(
+            vec![
+                (submission_pids, rlimits.cpu_time),
+                (interactor_pids, interactor_rlimits.cpu_time),
+            ],
+            rlimits.wall_time().max(interactor_rlimits.wall_time()),
+        );
+
+        let (submission_pid, interactor_pid) = (submission_pids.group, interactor_pids.group);
+
+        let interactor_stderr = {
+            let stderr = interactor.stderr.take().expect("no stderr");
+            thread::spawn(move || {
+                read_capped(stderr, interactor_rlimits.output_bytes, interactor_pid)
+            })
+        };
+
+        let (stderr, output_limit_exceeded) = read_capped(
+            submission.stderr.take().expect("no stderr"),
+            rlimits.output_bytes,
+            submission_pid,
+        )?;
+
+        let [timeout, interactor_timeout] =
+            finish(&[submission_pid, interactor_pid], watchdog)?[..]
+        else {
+            unreachable!("watchdog did not return timeouts");
+        };
+
+        let (exit_status, mut resource_usage) = resource::wait4(submission_pid)?;
+
+        let memory_limit_exceeded = match &cgroup {
+            Some(cgroup) => cgroup.account(&mut resource_usage)?,
+            None => false,
+        };
+
+        let denied = notifier.map(Notifier::stop).unwrap_or_default();
+        let (interactor_exit_status, interactor_resource_usage) = resource::wait4(interactor_pid)?;
+
+        let (interactor_stderr, interactor_output_limit_exceeded) = interactor_stderr
+            .join()
+            .expect("failed to read interactor stderr")?;
+
+        Ok((
+            Output {
+                exit_status,
+                stdout: Vec::new(),
+                stderr,
+                resource_usage,
+                timeout,
+                output_limit_exceeded,
+                memory_limit_exceeded,
+                forbidden_syscalls: denied.syscalls,
+                forbidden_paths: denied.paths,
+            },
+            Output {
+                exit_status: interactor_exit_status,
+                stdout: Vec::new(),
+                stderr: interactor_stderr,
+                resource_usage: interactor_resource_usage,
+                timeout: interactor_timeout,
+                output_limit_exceeded: interactor_output_limit_exceeded,
+                memory_limit_exceeded: false,
+                forbidden_syscalls: Vec::new(),
+                forbidden_paths: Vec::new(),
+            },
+        ))
+    }
+
     fn path(&self) -> &Path {
         self.dir.path()
     }
 
-    #[tracing::instrument(skip(stdin), err)]
-    fn exec(
+    fn spawn(
         &self,
         command: &Command,
-        stdin: Option<&[u8]>,
         rlimits: ResourceLimits,
         profile: Profile,
-    ) -> io::Result<Output> {
+        cgroup: Option<&Cgroup>,
+        [stdin, stdout, stderr]: [Stdio; 3],
+    ) -> io::Result<(process::Child, Pids, Option<Notifier>)> {
         let mut cmd = process::Command::new(&command.executable);
         cmd.args(&command.args)
             .current_dir(self.path())
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
+            .stdin(stdin)
+            .stdout(stdout)
+            .stderr(stderr);
+
+        if let Some(user) = self.user {
+            cmd.uid(user.uid).gid(user.gid);
+        }
+
+        let cgroup = cgroup.map(Cgroup::procs_fd);
+        let landlock = match profile {
+            Profile::Build | Profile::Run => self.landlock.as_ref().map(Ruleset::fd),
+            Profile::Judge => None,
+        };
+
+        let namespaces = self
+            .namespaces
+            .clone()
+            .filter(|namespaces| namespaces.enabled(profile));
+        let pid_pipe = namespaces.as_ref().map(|_| pipe()).transpose()?;
+        let pid_fd = pid_pipe.as_ref().map(|(_, write)| write.as_raw_fd());
+
+        // Compilers write temporary files, which Landlock only allows in the sandbox directory,
+        // where a private /tmp is mounted in namespaces
+        if profile == Profile::Build {
+            match namespaces {
+                Some(_) => cmd.env("TMPDIR", "/tmp"),
+                None => cmd.env("TMPDIR", self.path()),
+            };
+        }
+
+        // Landlock would fail opens of test data silently, so they are checked by the notifier as
+        // well. In namespaces the files cannot be found at all.
+        let opens = match (profile, &namespaces) {
+            (Profile::Run, None) if !self.protected.is_empty() => Some(OpenCheck {
+                allowlist: self.allowlist.clone(),
+                protected: self.protected.as_slice().into(),
+            }),
+            _ => None,
+        };
+
+        // Denied system calls are reported to the judge through a listener the run sends back
+        let notify_socket = match (profile, self.seccomp) {
+            (Profile::Run, SeccompMode::Trap | SeccompMode::Kill) => Some(seccomp::socketpair()?),
+            (Profile::Run, SeccompMode::Errno) if opens.is_some() => Some(seccomp::socketpair()?),
+            _ => None,
+        };
+        let notify_fd = notify_socket.as_ref().map(|(_, socket)| socket.as_raw_fd());
+
+        let filter = match (profile, notify_fd) {
+            (Profile::Run, Some(socket_fd)) => Some(Arc::new(seccomp::notify_filter(
+                &self.allowlist,
+                socket_fd,
+                self.seccomp,
+                opens.is_some(),
+            ))),
+            (Profile::Run, None) => Some(self.allowlist.filter()),
+            (Profile::Build, _) => Some(seccomp::build_filter()),
+            (Profile::Judge, _) => None,
+        };
 
         unsafe {
-            cmd.pre_exec(move || sandbox(rlimits, profile));
+            cmd.pre_exec(move || {
+                let namespaces = namespaces.as_deref().zip(pid_fd);
+                let seccomp = filter.as_deref().map(|filter| (filter, notify_fd));
+                sandbox(rlimits, profile, cgroup, landlock, namespaces, seccomp)
+            });
         }
 
-        let mut child = cmd.spawn()?;
+        let child = cmd.spawn()?;
+        let group = child.id() as i32;
+
+        let (pid_read, notify_read) = (
+            pid_pipe.map(|(read, _)| read),
+            notify_socket.map(|(read, _)| read),
+        );
 
-        if let Some(stdin) = stdin {
-            if let Err(e) = child.stdin.take().expect("no stdin").write_all(stdin) {
-                if e.kind() != io::ErrorKind::BrokenPipe {
-                    return Err(e);
+        let received = (|| {
+            // The supervisor reports the pid of the command once it has forked it
+            let command = match pid_read {
+                Some(read) => {
+                    let mut pid = [0; size_of::<i32>()];
+                    File::from(read).read_exact(&mut pid)?;
+                    i32::from_ne_bytes(pid)
                 }
+                None => group,
+            };
+
+            let listener = notify_read.map(seccomp::receive_listener).transpose()?;
+            io::Result::Ok((command, listener))
+        })();
+
+        match received {
+            Ok((command, listener)) => Ok((
+                child,
+                Pids { group, command },
+                listener.map(|listener| Notifier::start(listener, self.seccomp, group, opens)),
+            )),
+            Err(e) => {
+                watchdog::kill_group(group);
+                resource::wait4(group).ok();
+                Err(e)
+            }
+        }
+    }
+
+    /// Creates a cgroup for a submission, if cgroups are enabled.
+    fn cgroup(&self, rlimits: ResourceLimits, profile: Profile) -> io::Result<Option<Cgroup>> {
+        match (&self.cgroup_root, profile) {
+            (Some(root), Profile::Run) => {
+                let processes = rlimits
+                    .processes
+                    .unwrap_or(resource::RUN_DEFAULTS.processes);
+                Cgroup::new(root, rlimits, processes).map(Some)
             }
+            _ => Ok(None),
         }
+    }
 
-        let (stdout, stderr) = {
-            let (mut stdout, mut stderr) = (
-                child.stdout.take().expect("no stdout"),
-                child.stderr.take().expect("no stderr"),
-            );
-            let (mut stdout_buf, mut stderr_buf) = (Vec::new(), Vec::new());
+    /// Runs a command under a profile, e.g. a checker as a trusted judge program.
+    #[tracing::instrument(skip(stdin), err)]
+    pub fn exec(
+        &self,
+        command: &Command,
+        stdin: Option<&[u8]>,
+        rlimits: ResourceLimits,
+        profile: Profile,
+    ) -> io::Result<Output> {
+        let cgroup = self.cgroup(rlimits, profile)?;
 
-            stdout.read_to_end(&mut stdout_buf)?;
-            stderr.read_to_end(&mut stderr_buf)?;
+        let (mut child, pids, notifier) = self.spawn(
+            command,
+            rlimits,
+            profile,
+            cgroup.as_ref(),
+            [Stdio::piped(), Stdio::piped(), Stdio::piped()],
+        )?;
 
-            (stdout_buf, stderr_buf)
-        };
+        let watchdog = Watchdog::start(vec![(pids, rlimits.cpu_time)], rlimits.wall_time());
+
+        let pid = pids.group;
+        let (stdin_pipe, stdout_pipe, stderr_pipe) = (
+            child.stdin.take().expect("no stdin"),
+            child.stdout.take().expect("no stdout"),
+            child.stderr.take().expect("no stderr"),
+        );
+
+        // The pipes are drained concurrently, so that a process blocked on writing to one of them
+        // cannot deadlock the judge
+        let ((stdout, stdout_exceeded), (stderr, stderr_exceeded)) = thread::scope(|scope| {
+            let writer = scope.spawn(|| write_stdin(stdin_pipe, stdin.unwrap_or_default()));
+            let stderr = scope.spawn(|| read_capped(stderr_pipe, rlimits.output_bytes, pid));
+            let stdout = read_capped(stdout_pipe, rlimits.output_bytes, pid);
 
-        let (exit_status, resource_usage) = resource::wait4(child.id() as i32)?;
+            let stderr = stderr.join().expect("failed to read stderr");
+            writer.join().expect("failed to write stdin")?;
+            io::Result::Ok((stdout?, stderr?))
+        })?;
+
+        let timeout = finish(&[pid], watchdog)?[0];
+        let (exit_status, mut resource_usage) = resource::wait4(pid)?;
+        let denied = notifier.map(Notifier::stop).unwrap_or_default();
+
+        let memory_limit_exceeded = match &cgroup {
+            Some(cgroup) => cgroup.account(&mut resource_usage)?,
+            None => false,
+        };
 
         Ok(Output {
             exit_status,
             stdout,
             stderr,
             resource_usage,
+            timeout,
+            output_limit_exceeded: stdout_exceeded || stderr_exceeded,
+            memory_limit_exceeded,
+            forbidden_syscalls: denied.syscalls,
+            forbidden_paths: denied.paths,
         })
     }
 }
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
-enum Profile {
+fn write_stdin(mut pipe: impl Write, stdin: &[u8]) -> io::Result<()> {
+    match pipe.write_all(stdin) {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Reads a pipe to the end, killing the process group if more than `limit` bytes are written.
+/// Returns whether the limit was exceeded.
+fn read_capped(pipe: impl Read, limit: u64, pgid: i32) -> io::Result<(Vec<u8>, bool)> {
+    let mut buf = Vec::new();
+    pipe.take(limit + 1).read_to_end(&mut buf)?;
+
+    let exceeded = buf.len() as u64 > limit;
+    if exceeded {
+        tracing::debug!("output limit exceeded, killing {pgid}");
+        watchdog::kill_group(pgid);
+        buf.truncate(limit as usize);
+    }
+
+    Ok((buf, exceeded))
+}
+
+/// Waits for processes to exit, then kills anything left behind in their process groups and
+/// stops the watchdog, returning the limit each process was killed for exceeding. The processes
+/// still have to be reaped afterwards.
+fn finish(pids: &[i32], watchdog: Watchdog) -> io::Result<Vec<Option<Timeout>>> {
+    for &pid in pids {
+        resource::wait_exited(pid)?;
+    }
+
+    pids.iter().copied().for_each(watchdog::kill_group);
+    Ok(watchdog.stop())
+}
+
+/// The name and description of a signal which a crashed process may have been killed by.
+pub fn signal_name(signal: i32) -> Option<(&'static str, &'static str)> {
+    Some(match signal {
+        libc::SIGSEGV => ("SIGSEGV", "segmentation fault"),
+        libc::SIGFPE => ("SIGFPE", "arithmetic error, e.g. division by zero"),
+        libc::SIGABRT => ("SIGABRT", "aborted"),
+        libc::SIGSYS => ("SIGSYS", "forbidden system call"),
+        libc::SIGBUS => ("SIGBUS", "bus error"),
+        libc::SIGILL => ("SIGILL", "illegal instruction"),
+        libc::SIGKILL => ("SIGKILL", "killed"),
+        libc::SIGPIPE => ("SIGPIPE", "broken pipe"),
+        libc::SIGTERM => ("SIGTERM", "terminated"),
+        libc::SIGTRAP => ("SIGTRAP", "trace trap"),
+        _ => return None,
+    })
+}
+
+/// Creates a pipe, returning the read and write ends.
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+    }
+}
+
+/// The processes of a spawned command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Pids {
+    /// The spawned process, which leads the process group
+    group: i32,
+    /// The process running the command, which is a child of the spawned process if it is
+    /// supervised in namespaces
+    command: i32,
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
     Build,
     Run,
+    /// Task-supplied programs which are trusted, but still subject to resource limits
+    Judge,
 }
 
-fn sandbox(rlimits: ResourceLimits, profile: Profile) -> io::Result<()> {
-    use io::{Error, ErrorKind};
+fn sandbox(
+    rlimits: ResourceLimits,
+    profile: Profile,
+    cgroup: Option<RawFd>,
+    landlock: Option<RawFd>,
+    namespaces: Option<(&Namespaces, RawFd)>,
+    seccomp: Option<(&BpfProgram, Option<RawFd>)>,
+) -> io::Result<()> {
+    // Run in a new process group, so that the watchdog can kill any child processes too
+    if unsafe { libc::setpgid(0, 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if let Some(procs_fd) = cgroup {
+        cgroup::join(procs_fd)?;
+    }
+
+    let defaults = match profile {
+        Profile::Build | Profile::Judge => resource::BUILD_DEFAULTS,
+        Profile::Run => resource::RUN_DEFAULTS,
+    };
+
+    // Process limits are inherited by a new user namespace as limits for the whole namespace,
+    // so they are only set once inside of it
+    if let Some((namespaces, pid_fd)) = namespaces {
+        namespaces.enter(pid_fd)?;
+    }
+
+    rlimits.set(defaults, cgroup.is_some())?;
 
-    rlimits.set()?;
+    if let Some(ruleset_fd) = landlock {
+        landlock::restrict_self(ruleset_fd)?;
+    }
 
-    if let Profile::Run = profile {
-        seccomp::apply_filters().map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+    match seccomp {
+        Some((filter, Some(socket_fd))) => seccomp::apply_notify_filter(filter, socket_fd)?,
+        Some((filter, None)) => seccomp::apply_filter(filter)?,
+        None => {}
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+
+    use super::*;
+    use crate::judge::Config;
+
+    /// Creates a sandbox for a shell submission, with extra judge configuration.
+    fn sandbox(config: &str) -> Sandbox {
+        let config: Config = toml::from_str(&format!(
+            "{config}\n[[language]]\nname = \"sh\"\nfilename = \"submission.sh\"\nrun = \"/bin/sh submission.sh\""
+        ))
+        .unwrap();
+        Sandbox::for_submission(&config, &config.languages[0]).unwrap()
+    }
+
+    #[test]
+    #[ignore = "needs Landlock"]
+    fn runs_cannot_read_test_data() {
+        let mut sandbox = sandbox("");
+        assert!(sandbox.landlock.is_some(), "Landlock is unsupported");
+
+        let tests = tempfile::tempdir().unwrap();
+        let answer = tests.path().join("1.out");
+        fs::write(&answer, "secret answer").unwrap();
+        sandbox.protect(tests.path()).unwrap();
+
+        let rlimits: ResourceLimits = toml::from_str("time = 1\nmemory = \"256MiB\"").unwrap();
+        let cat = Command::new("cat", [&answer]);
+
+        let output = sandbox.run(&cat, &[], rlimits).unwrap();
+        assert!(!output.exit_status.success());
+        assert!(output.stdout.is_empty());
+        assert_eq!(output.forbidden_paths, [answer.canonicalize().unwrap()]);
+
+        // Nor can builds
+        let output = sandbox.build(&cat, rlimits).unwrap();
+        assert!(!output.exit_status.success());
+        assert!(output.stdout.is_empty());
+
+        // Files in the sandbox directory are still readable
+        sandbox.write("input.txt", "sandboxed").unwrap();
+        let output = sandbox
+            .run(&Command::new("cat", ["input.txt"]), &[], rlimits)
+            .unwrap();
+        assert!(output.exit_status.success());
+        assert_eq!(output.stdout, b"sandboxed");
+        assert!(output.forbidden_paths.is_empty());
+    }
+
+    #[test]
+    fn protection_is_enforced_or_refused() {
+        // Only checking the opens of a run could be raced by another of its threads
+        let mut sandbox = Sandbox::new().unwrap();
+        let tests = tempfile::tempdir().unwrap();
+
+        let error = sandbox.protect(tests.path()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn pool_users_with_capabilities() {
+        assert_eq!(unsafe { libc::geteuid() }, 0, "not running as root");
+
+        // Capabilities belong to threads, so the rest of the tests keep theirs
+        thread::spawn(|| {
+            #[repr(C)]
+            struct Header {
+                version: u32,
+                pid: i32,
+            }
+            #[repr(C)]
+            struct Data {
+                effective: u32,
+                permitted: u32,
+                inheritable: u32,
+            }
+
+            // CAP_CHOWN, CAP_SETGID and CAP_SETUID, without CAP_DAC_OVERRIDE or CAP_FOWNER
+            let caps = (1 << 0) | (1 << 6) | (1 << 7);
+            let header = Header {
+                version: 0x20080522,
+                pid: 0,
+            };
+            let data = [caps, 0].map(|caps| Data {
+                effective: caps,
+                permitted: caps,
+                inheritable: 0,
+            });
+            assert_eq!(
+                unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) },
+                0
+            );
+
+            let config = "users = { uid = 31000, gid = 31000, size = 2 }";
+            let judge = {
+                let config: Config = toml::from_str(&format!(
+                    "{config}\n[[language]]\nname = \"sh\"\nfilename = \"checker.sh\"\nrun = \"/bin/sh checker.sh\""
+                ))
+                .unwrap();
+                Sandbox::for_judge(&config, &config.languages[0]).unwrap()
+            };
+            judge.write("1.ans", "secret answer").unwrap();
+
+            let sandbox = sandbox(config);
+            let answer = judge.path().join("1.ans");
+            sandbox
+                .write("submission.sh", format!("echo ok > out; cat out {}", answer.display()))
+                .unwrap();
+
+            let rlimits: ResourceLimits = toml::from_str("time = 1\nmemory = \"256MiB\"").unwrap();
+            // Builds may run other programs
+            let sh = Command::new("/bin/sh", ["submission.sh"]);
+            let output = sandbox.build(&sh, rlimits).unwrap();
+
+            // The submission can use its own directory, but not read that of the checker
+            assert_eq!(output.stdout, b"ok\n");
+            assert!(!output.exit_status.success());
+            sandbox.remove("out").unwrap();
+
+            let path = sandbox.path().to_owned();
+            drop(sandbox);
+            assert!(!path.exists());
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn forbidden_syscalls_are_reported() {
+        let sandbox = sandbox("seccomp = \"kill\"");
+
+        let rlimits: ResourceLimits = toml::from_str("time = 1\nmemory = \"256MiB\"").unwrap();
+        // Runs may not fork the shell's child processes
+        let sh = Command::new("/bin/sh", ["-c", "true; cat"]);
+
+        let output = sandbox.run(&sh, &[], rlimits).unwrap();
+        assert!(!output.forbidden_syscalls.is_empty());
+        assert_eq!(output.exit_status.signal(), Some(libc::SIGKILL));
+    }
+
+    #[test]
+    #[ignore = "needs unprivileged user namespaces"]
+    fn namespaces_isolate_commands() {
+        if let Err(e) = namespace::check(None) {
+            panic!("namespaces are unavailable: {e}");
+        }
+
+        let sandbox = sandbox("namespaces = [\"build\"]");
+        sandbox.write("submission", "").unwrap();
+
+        let rlimits: ResourceLimits = toml::from_str("time = 1\nmemory = \"256MiB\"").unwrap();
+        let sh = Command::new("/bin/sh", ["-c", "echo $$; ls /"]);
+
+        let output = sandbox.build(&sh, rlimits).unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(output.exit_status.success(), "{stdout}");
+
+        // The command is not the init process of its pid namespace, and only sees the sandbox
+        let mut lines = stdout.lines();
+        assert_eq!(lines.next(), Some("2"));
+        let root: Vec<_> = lines.collect();
+        assert!(root.contains(&"submission"), "{root:?}");
+        assert!(!root.contains(&"root"), "{root:?}");
+    }
+}
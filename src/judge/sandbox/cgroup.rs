@@ -0,0 +1,163 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::fs::OpenOptionsExt,
+    },
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Duration as StdDuration,
+};
+
+use time::Duration;
+
+use super::resource::{ResourceLimits, ResourceUsage};
+
+const CONTROLLERS: [&str; 3] = ["memory", "pids", "cpu"];
+const REMOVE_ATTEMPTS: usize = 10;
+const REMOVE_RETRY_DELAY: StdDuration = StdDuration::from_millis(10);
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Checks that a cgroup v2 directory can be used by the judge, enabling the controllers it needs
+/// for child cgroups.
+pub fn check(root: &Path) -> io::Result<()> {
+    let controllers = fs::read_to_string(root.join("cgroup.controllers"))?;
+
+    for controller in CONTROLLERS {
+        if !controllers.split_whitespace().any(|c| c == controller) {
+            return Err(io::Error::other(format!(
+                "{controller} controller is not available in {}",
+                root.display()
+            )));
+        }
+    }
+
+    let enable = CONTROLLERS.map(|controller| format!("+{controller}"));
+    fs::write(root.join("cgroup.subtree_control"), enable.join(" "))
+}
+
+/// A cgroup for a single process and its children, which is removed when dropped.
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+    procs: File,
+}
+
+impl Cgroup {
+    pub fn new(root: &Path, rlimits: ResourceLimits, processes: u64) -> io::Result<Self> {
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = root.join(format!("sandbox-{}-{n}", std::process::id()));
+        fs::create_dir(&path)?;
+
+        let cgroup = Cgroup {
+            procs: OpenOptions::new()
+                .write(true)
+                .custom_flags(libc::O_CLOEXEC)
+                .open(path.join("cgroup.procs"))?,
+            path,
+        };
+
+        cgroup.write("memory.max", rlimits.memory_bytes)?;
+        cgroup.write("memory.swap.max", 0).ok();
+        cgroup.write("pids.max", processes)?;
+
+        Ok(cgroup)
+    }
+
+    /// The file descriptor of `cgroup.procs`, which a child process writes `0` to in order to
+    /// join the cgroup before it executes.
+    pub fn procs_fd(&self) -> RawFd {
+        self.procs.as_raw_fd()
+    }
+
+    /// Replaces the resource usage measured by `wait4` with that of the whole cgroup, returning
+    /// whether a process was killed for exceeding the memory limit.
+    pub fn account(&self, resource_usage: &mut ResourceUsage) -> io::Result<bool> {
+        let cpu_stat = self.read("cpu.stat")?;
+        resource_usage.user_time = Duration::microseconds(stat(&cpu_stat, "user_usec")?);
+        resource_usage.sys_time = Duration::microseconds(stat(&cpu_stat, "system_usec")?);
+
+        // memory.peak is only available since Linux 5.19
+        match self.read("memory.peak") {
+            Ok(peak) => resource_usage.memory_bytes = peak.trim().parse().map_err(invalid)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        Ok(stat(&self.read("memory.events")?, "oom_kill")? > 0)
+    }
+
+    fn read(&self, file: &str) -> io::Result<String> {
+        fs::read_to_string(self.path.join(file))
+    }
+
+    fn write(&self, file: &str, value: impl ToString) -> io::Result<()> {
+        fs::write(self.path.join(file), value.to_string())
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // Kill anything left behind, e.g. processes which escaped their process group
+        self.write("cgroup.kill", 1).ok();
+
+        for _ in 0..REMOVE_ATTEMPTS {
+            match fs::remove_dir(&self.path) {
+                Ok(()) => return,
+                Err(e) if e.raw_os_error() == Some(libc::EBUSY) => {
+                    thread::sleep(REMOVE_RETRY_DELAY)
+                }
+                Err(e) => {
+                    tracing::warn!("failed to remove cgroup {}: {e}", self.path.display());
+                    return;
+                }
+            }
+        }
+
+        tracing::warn!("failed to remove busy cgroup {}", self.path.display());
+    }
+}
+
+/// Joins a cgroup from a child process before it executes.
+pub fn join(procs_fd: RawFd) -> io::Result<()> {
+    if unsafe { libc::write(procs_fd, b"0".as_ptr().cast(), 1) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Reads a value from a flat keyed file such as `cpu.stat` or `memory.events`.
+fn stat(contents: &str, key: &str) -> io::Result<i64> {
+    contents
+        .lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix(' '))
+        .ok_or_else(|| invalid(format!("missing {key}")))?
+        .trim()
+        .parse()
+        .map_err(invalid)
+}
+
+fn invalid(error: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_keyed() {
+        let cpu_stat = "usage_usec 1500\nuser_usec 1000\nsystem_usec 500\n";
+        assert_eq!(stat(cpu_stat, "usage_usec").unwrap(), 1500);
+        assert_eq!(stat(cpu_stat, "user_usec").unwrap(), 1000);
+        assert_eq!(stat(cpu_stat, "system_usec").unwrap(), 500);
+
+        let memory_events = "low 0\nhigh 0\nmax 3\noom 1\noom_kill 1\noom_group_kill 0\n";
+        assert_eq!(stat(memory_events, "oom_kill").unwrap(), 1);
+        assert!(stat(memory_events, "missing").is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    fs::OpenOptions,
+    io,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::fs::OpenOptionsExt,
+    },
+    path::{Path, PathBuf},
+    ptr,
+};
+
+use once_cell::sync::Lazy;
+
+// From linux/landlock.h
+const CREATE_RULESET_VERSION: u32 = 1 << 0;
+const RULE_PATH_BENEATH: libc::c_int = 1;
+
+const ACCESS_EXECUTE: u64 = 1 << 0;
+const ACCESS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_READ_FILE: u64 = 1 << 2;
+const ACCESS_READ_DIR: u64 = 1 << 3;
+const ACCESS_TRUNCATE: u64 = 1 << 14;
+const ACCESS_IOCTL_DEV: u64 = 1 << 15;
+
+/// Rights which apply to files rather than directories, any others are rejected in rules for files
+const FILE_ACCESS: u64 =
+    ACCESS_EXECUTE | ACCESS_WRITE_FILE | ACCESS_READ_FILE | ACCESS_TRUNCATE | ACCESS_IOCTL_DEV;
+const READ_ACCESS: u64 = ACCESS_EXECUTE | ACCESS_READ_FILE | ACCESS_READ_DIR;
+
+/// The Landlock ABI version supported by the kernel, if any.
+static ABI: Lazy<Option<i64>> = Lazy::new(|| {
+    let version = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            ptr::null::<RulesetAttr>(),
+            0,
+            CREATE_RULESET_VERSION,
+        )
+    };
+
+    if version < 0 {
+        tracing::warn!(
+            "Landlock is unsupported, submissions can read any file the judge can: {}",
+            io::Error::last_os_error()
+        );
+        None
+    } else {
+        Some(version)
+    }
+});
+
+/// Whether the kernel supports Landlock.
+pub fn supported() -> bool {
+    ABI.is_some()
+}
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: RawFd,
+}
+
+/// A Landlock ruleset restricting filesystem access to a writable sandbox directory and
+/// read-only paths such as language runtimes.
+#[derive(Debug)]
+pub struct Ruleset(OwnedFd);
+
+impl Ruleset {
+    /// Creates a ruleset, or returns `None` if Landlock is unsupported. Readable paths which do not
+    /// exist are ignored.
+    pub fn new(writable: &Path, readable: &[PathBuf]) -> io::Result<Option<Self>> {
+        let Some(abi) = *ABI else {
+            return Ok(None);
+        };
+
+        let handled_access_fs = handled_access(abi);
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &RulesetAttr { handled_access_fs } as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let ruleset = Ruleset(unsafe { OwnedFd::from_raw_fd(fd as RawFd) });
+        ruleset.allow(writable, handled_access_fs)?;
+
+        for path in readable {
+            match ruleset.allow(path, READ_ACCESS & handled_access_fs) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    tracing::debug!("skipping missing readable path {}", path.display())
+                }
+                result => result?,
+            }
+        }
+
+        Ok(Some(ruleset))
+    }
+
+    /// The file descriptor of the ruleset, which a child process enforces with [`restrict_self`]
+    /// before it executes.
+    pub fn fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+
+    fn allow(&self, path: &Path, access: u64) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_PATH | libc::O_CLOEXEC)
+            .open(path)?;
+
+        let allowed_access = if file.metadata()?.is_dir() {
+            access
+        } else {
+            access & FILE_ACCESS
+        };
+
+        let attr = PathBeneathAttr {
+            allowed_access,
+            parent_fd: file.as_raw_fd(),
+        };
+
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                self.fd(),
+                RULE_PATH_BENEATH,
+                &attr as *const PathBeneathAttr,
+                0,
+            )
+        };
+
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Enforces a ruleset on a child process before it executes.
+pub fn restrict_self(ruleset_fd: RawFd) -> io::Result<()> {
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset_fd, 0) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Every filesystem access right known to a Landlock ABI version, so that anything not allowed by
+/// a rule is denied.
+fn handled_access(abi: i64) -> u64 {
+    // Version 1 handles executing, reading, writing and creating or removing files
+    let mut access = (1 << 13) - 1;
+
+    if abi >= 2 {
+        // Linking or renaming files between directories
+        access |= 1 << 13;
+    }
+    if abi >= 3 {
+        access |= ACCESS_TRUNCATE;
+    }
+    if abi >= 5 {
+        access |= ACCESS_IOCTL_DEV;
+    }
+
+    access
+}
@@ -0,0 +1,376 @@
+use std::{
+    ffi::{CStr, CString},
+    fs::{self, File},
+    io,
+    mem::MaybeUninit,
+    os::{
+        fd::RawFd,
+        unix::{self, ffi::OsStrExt},
+    },
+    path::{Path, PathBuf},
+    ptr,
+};
+
+use libc::c_ulong;
+
+use super::{Profile, User};
+
+const FLAGS: libc::c_int = libc::CLONE_NEWUSER
+    | libc::CLONE_NEWPID
+    | libc::CLONE_NEWNET
+    | libc::CLONE_NEWIPC
+    | libc::CLONE_NEWUTS
+    | libc::CLONE_NEWNS;
+
+/// The user and group ID inside the namespaces, which is not root so that commands lose their
+/// capabilities when they execute
+const SANDBOX_ID: u32 = 1000;
+const TMP_OPTIONS: &CStr = c"size=256m,mode=1777";
+
+/// Checks that namespaces can be created as a user, which requires unprivileged user namespaces
+/// unless it is root.
+pub fn check(user: Option<User>) -> io::Result<()> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => unsafe {
+            if let Some(user) = user {
+                if libc::setgid(user.gid) < 0 || libc::setuid(user.uid) < 0 {
+                    libc::_exit(libc::EPERM);
+                }
+            }
+
+            let code = if libc::unshare(FLAGS) < 0 {
+                io::Error::last_os_error().raw_os_error().unwrap_or(1)
+            } else {
+                0
+            };
+            libc::_exit(code)
+        },
+        pid => {
+            let mut status = 0;
+            if unsafe { libc::waitpid(pid, &mut status, 0) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            match libc::WEXITSTATUS(status) {
+                0 => Ok(()),
+                errno => Err(io::Error::from_raw_os_error(errno)),
+            }
+        }
+    }
+}
+
+/// New user, pid, network, IPC, UTS and mount namespaces with the sandbox directory as the root,
+/// prepared before spawning so that entering them only needs system calls.
+#[derive(Debug)]
+pub struct Namespaces {
+    profiles: Vec<Profile>,
+    root: CString,
+    mounts: Vec<Mount>,
+    tmp: CString,
+    proc: CString,
+    uid_map: CString,
+    gid_map: CString,
+}
+
+/// A read-only bind mount of a path outside of the sandbox to the same path within it.
+#[derive(Debug)]
+struct Mount {
+    source: CString,
+    target: CString,
+    /// Flags to remount the bind mount as read-only with, which must keep the flags locked by the
+    /// original mount
+    flags: c_ulong,
+}
+
+impl Namespaces {
+    /// Creates mount points in the sandbox directory for the readable paths, skipping those which
+    /// do not exist. Symbolic links such as `/lib -> usr/lib` are recreated instead.
+    pub fn new(
+        root: &Path,
+        readable: &[PathBuf],
+        profiles: Vec<Profile>,
+        user: Option<User>,
+    ) -> io::Result<Self> {
+        let mut mounts = Vec::new();
+
+        for path in readable {
+            let target = root.join(path.strip_prefix("/").map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("readable path {} is not absolute", path.display()),
+                )
+            })?);
+
+            let metadata = match fs::symlink_metadata(path) {
+                Ok(metadata) => metadata,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            if metadata.is_symlink() {
+                unix::fs::symlink(fs::read_link(path)?, &target)?;
+                continue;
+            } else if metadata.is_dir() {
+                fs::create_dir_all(&target)?;
+            } else {
+                File::create(&target)?;
+            }
+
+            mounts.push(Mount {
+                source: c_path(path)?,
+                target: c_path(&target)?,
+                flags: remount_flags(path)?,
+            });
+        }
+
+        let (tmp, proc) = (root.join("tmp"), root.join("proc"));
+        fs::create_dir_all(&tmp)?;
+        fs::create_dir_all(&proc)?;
+
+        // Commands enter the namespaces after switching to their user
+        let User { uid, gid } = user.unwrap_or_else(|| unsafe {
+            User {
+                uid: libc::getuid(),
+                gid: libc::getgid(),
+            }
+        });
+
+        Ok(Namespaces {
+            profiles,
+            root: c_path(root)?,
+            mounts,
+            tmp: c_path(&tmp)?,
+            proc: c_path(&proc)?,
+            uid_map: CString::new(format!("{SANDBOX_ID} {uid} 1"))?,
+            gid_map: CString::new(format!("{SANDBOX_ID} {gid} 1"))?,
+        })
+    }
+
+    pub fn enabled(&self, profile: Profile) -> bool {
+        self.profiles.contains(&profile)
+    }
+
+    /// Enters the namespaces from a child process before it executes. The child stays outside of
+    /// the pid namespace as a supervisor, which forks an init process and the process that goes
+    /// on to execute, writes the pid of the latter to `pid_fd` and exits in the same way as it.
+    /// This only returns in the process that goes on to execute.
+    pub fn enter(&self, pid_fd: RawFd) -> io::Result<()> {
+        unsafe {
+            cvt(libc::unshare(FLAGS))?;
+        }
+
+        // Switching user makes a process undumpable, which leaves its /proc files owned by root
+        unsafe {
+            cvt(libc::prctl(libc::PR_SET_DUMPABLE, 1))?;
+        }
+
+        write_file(c"/proc/self/setgroups", c"deny")?;
+        write_file(c"/proc/self/uid_map", &self.uid_map)?;
+        write_file(c"/proc/self/gid_map", &self.gid_map)?;
+
+        unsafe {
+            cvt(libc::prctl(libc::PR_SET_DUMPABLE, 0))?;
+        }
+
+        // The first process in the pid namespace is its init, which would ignore signals it sends
+        // itself without handlers, so it is kept separate from the command
+        let init = match unsafe { libc::fork() } {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => init(),
+            pid => pid,
+        };
+
+        match unsafe { libc::fork() } {
+            -1 => {
+                unsafe { libc::kill(init, libc::SIGKILL) };
+                Err(io::Error::last_os_error())
+            }
+            0 => self.mount(),
+            pid => unsafe {
+                libc::write(pid_fd, ptr::addr_of!(pid).cast(), size_of_val(&pid));
+                close_all();
+                supervise(pid, init)
+            },
+        }
+    }
+
+    /// Mounts the root filesystem and moves into it, once in the namespaces.
+    fn mount(&self) -> io::Result<()> {
+        let null = ptr::null::<libc::c_char>();
+
+        unsafe {
+            // Keep the mounts from propagating back to the host
+            cvt(libc::mount(
+                null,
+                c"/".as_ptr(),
+                null,
+                libc::MS_REC | libc::MS_PRIVATE,
+                ptr::null(),
+            ))?;
+
+            // pivot_root requires the new root to be a mount point
+            cvt(libc::mount(
+                self.root.as_ptr(),
+                self.root.as_ptr(),
+                null,
+                libc::MS_BIND | libc::MS_REC,
+                ptr::null(),
+            ))?;
+
+            for mount in self.mounts.iter() {
+                cvt(libc::mount(
+                    mount.source.as_ptr(),
+                    mount.target.as_ptr(),
+                    null,
+                    libc::MS_BIND | libc::MS_REC,
+                    ptr::null(),
+                ))?;
+                cvt(libc::mount(
+                    null,
+                    mount.target.as_ptr(),
+                    null,
+                    mount.flags,
+                    ptr::null(),
+                ))?;
+            }
+
+            cvt(libc::mount(
+                c"tmpfs".as_ptr(),
+                self.tmp.as_ptr(),
+                c"tmpfs".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV,
+                TMP_OPTIONS.as_ptr().cast(),
+            ))?;
+            cvt(libc::mount(
+                c"proc".as_ptr(),
+                self.proc.as_ptr(),
+                c"proc".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                ptr::null(),
+            ))?;
+
+            // Stack the new root on top of the old one, then detach the old one from under it
+            cvt(libc::chdir(self.root.as_ptr()))?;
+            cvt(libc::syscall(libc::SYS_pivot_root, c".".as_ptr(), c".".as_ptr()) as i32)?;
+            cvt(libc::umount2(c".".as_ptr(), libc::MNT_DETACH))?;
+            cvt(libc::chdir(c"/".as_ptr()))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reaps orphaned processes in the pid namespace until it is killed by the supervisor.
+fn init() -> ! {
+    extern "C" fn wake(_: libc::c_int) {}
+
+    unsafe {
+        close_all();
+        libc::signal(
+            libc::SIGCHLD,
+            wake as extern "C" fn(_) as libc::sighandler_t,
+        );
+
+        loop {
+            if libc::wait(ptr::null_mut()) < 0 {
+                libc::pause();
+            }
+        }
+    }
+}
+
+/// Waits for the command to exit, then kills the init process, tearing down the pid namespace
+/// with anything left behind in it, and exits with the status of the command.
+unsafe fn supervise(pid: libc::pid_t, init: libc::pid_t) -> ! {
+    let mut status = 0;
+    while libc::waitpid(pid, &mut status, 0) < 0 {
+        if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            libc::_exit(127);
+        }
+    }
+
+    libc::kill(init, libc::SIGKILL);
+    libc::waitpid(init, ptr::null_mut(), 0);
+
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        let no_core = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        libc::setrlimit(libc::RLIMIT_CORE, &no_core);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+        libc::_exit(128 + signal);
+    }
+
+    libc::_exit(libc::WEXITSTATUS(status))
+}
+
+/// Closes every file descriptor, so that the judge sees the pipes of a command close once the
+/// command exits.
+unsafe fn close_all() {
+    libc::syscall(libc::SYS_close_range, 0, libc::c_uint::MAX, 0);
+}
+
+fn write_file(path: &CStr, contents: &CStr) -> io::Result<()> {
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+        cvt(fd)?;
+
+        let contents = contents.to_bytes();
+        let written = libc::write(fd, contents.as_ptr().cast(), contents.len());
+        libc::close(fd);
+
+        cvt(written as i32)
+    }
+}
+
+fn remount_flags(path: &Path) -> io::Result<c_ulong> {
+    let path = c_path(path)?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    let stat = unsafe {
+        cvt(libc::statvfs(path.as_ptr(), stat.as_mut_ptr()))?;
+        stat.assume_init()
+    };
+
+    let mut flags = libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY;
+
+    for (st, ms) in [
+        (libc::ST_NOSUID, libc::MS_NOSUID),
+        (libc::ST_NODEV, libc::MS_NODEV),
+        (libc::ST_NOEXEC, libc::MS_NOEXEC),
+        (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+    ] {
+        if stat.f_flag & st != 0 {
+            flags |= ms;
+        }
+    }
+
+    flags |= if stat.f_flag & libc::ST_NOATIME != 0 {
+        libc::MS_NOATIME
+    } else if stat.f_flag & libc::ST_RELATIME != 0 {
+        libc::MS_RELATIME
+    } else {
+        libc::MS_STRICTATIME
+    };
+
+    Ok(flags)
+}
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+fn cvt(result: libc::c_int) -> io::Result<()> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
@@ -1,13 +1,50 @@
 use std::{io, os::unix::process::ExitStatusExt, process::ExitStatus};
 
-use rlimit::{setrlimit, Resource};
-use serde::Deserialize;
+use rlimit::{getrlimit, setrlimit, Resource, INFINITY};
+use serde::{Deserialize, Serialize};
 use time::Duration;
 
+/// Extra CPU time given by the rlimit, which is only a backstop for the judge's own polling
 const RLIMIT_CPU_TOLERANCE: u64 = 1;
 const RLIMIT_DATA_TOLERANCE: u64 = 1000;
+/// The default wall-clock time limit, as a multiple of the CPU time limit
+const WALL_TIME_MULTIPLIER: i32 = 3;
+const MIN_WALL_TIME: Duration = Duration::SECOND;
+const DEFAULT_OUTPUT_BYTES: u64 = 64 << 20;
+
+/// Defaults for the limits which are not given in the contest, depending on the sandbox profile.
+#[derive(Debug, Clone, Copy)]
+pub struct DefaultLimits {
+    /// `RLIMIT_NPROC` counts every process and thread of the user running the judge, not just
+    /// those in the sandbox, so it has to leave room for the judge itself
+    pub processes: u64,
+    pub file_size_bytes: u64,
+    pub open_files: u64,
+    /// The stack is limited to the memory limit if this is not set
+    pub stack: Option<StackLimit>,
+}
+
+pub const BUILD_DEFAULTS: DefaultLimits = DefaultLimits {
+    processes: 1024,
+    file_size_bytes: 256 << 20,
+    open_files: 256,
+    stack: Some(StackLimit::Bytes(8 << 20)),
+};
+
+pub const RUN_DEFAULTS: DefaultLimits = DefaultLimits {
+    processes: 256,
+    file_size_bytes: 16 << 20,
+    open_files: 64,
+    stack: None,
+};
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum StackLimit {
+    Unlimited,
+    Bytes(u64),
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ResourceUsage {
     pub user_time: Duration,
     pub sys_time: Duration,
@@ -20,26 +57,165 @@ impl ResourceUsage {
     }
 }
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Deserialize)]
+/// Limits are written with units, e.g. `time: 1500ms` and `memory: 256MiB`. Plain numbers are
+/// taken as seconds and bytes.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct ResourceLimits {
-    pub cpu_seconds: u64,
+    #[serde(
+        rename = "time",
+        alias = "cpu_seconds",
+        deserialize_with = "units::duration",
+        serialize_with = "units::serialize_duration"
+    )]
+    pub cpu_time: Duration,
+    #[serde(
+        rename = "memory",
+        alias = "memory_bytes",
+        deserialize_with = "units::bytes"
+    )]
     pub memory_bytes: u64,
+    /// Defaults to a multiple of `cpu_time`
+    #[serde(
+        default,
+        alias = "wall_seconds",
+        deserialize_with = "units::optional_duration",
+        serialize_with = "units::serialize_optional_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub wall_time: Option<Duration>,
+    /// The maximum size of stdout and stderr each
+    #[serde(
+        rename = "output",
+        default = "default_output_bytes",
+        deserialize_with = "units::bytes"
+    )]
+    pub output_bytes: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processes: Option<u64>,
+    #[serde(
+        default,
+        rename = "file_size",
+        deserialize_with = "units::optional_bytes",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub file_size_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_files: Option<u64>,
+    /// Either a size or `unlimited`
+    #[serde(
+        default,
+        deserialize_with = "units::optional_stack",
+        serialize_with = "units::serialize_optional_stack",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub stack: Option<StackLimit>,
+}
+
+fn default_output_bytes() -> u64 {
+    DEFAULT_OUTPUT_BYTES
 }
 
 impl ResourceLimits {
-    pub fn set(&self) -> io::Result<()> {
+    pub fn wall_time(&self) -> Duration {
+        self.wall_time
+            .unwrap_or((self.cpu_time * WALL_TIME_MULTIPLIER).max(MIN_WALL_TIME))
+    }
+
+    /// Sets the limits of the current process. The memory and process limits are left to the
+    /// cgroup if the process is in one, as `RLIMIT_NPROC` is per user and `RLIMIT_DATA` makes
+    /// allocations fail rather than reporting the memory limit as exceeded.
+    pub fn set(&self, defaults: DefaultLimits, in_cgroup: bool) -> io::Result<()> {
+        // Rounded up to whole seconds, the judge enforces the precise limit
+        let cpu_seconds = (self.cpu_time.whole_milliseconds() as u64).div_ceil(1000);
+
         setrlimit(
             Resource::CPU,
-            self.cpu_seconds,
-            self.cpu_seconds + RLIMIT_CPU_TOLERANCE,
+            cpu_seconds + RLIMIT_CPU_TOLERANCE,
+            cpu_seconds + RLIMIT_CPU_TOLERANCE + 1,
         )?;
-        setrlimit(
-            Resource::DATA,
-            self.memory_bytes,
-            self.memory_bytes + RLIMIT_DATA_TOLERANCE,
+        if !in_cgroup {
+            setrlimit(
+                Resource::DATA,
+                self.memory_bytes,
+                self.memory_bytes + RLIMIT_DATA_TOLERANCE,
+            )?;
+            limit(
+                Resource::NPROC,
+                self.processes.unwrap_or(defaults.processes),
+            )?;
+        }
+
+        limit(
+            Resource::FSIZE,
+            self.file_size_bytes.unwrap_or(defaults.file_size_bytes),
         )?;
-        Ok(())
+        limit(
+            Resource::NOFILE,
+            self.open_files.unwrap_or(defaults.open_files),
+        )?;
+        limit(Resource::CORE, 0)?;
+
+        let stack = self
+            .stack
+            .or(defaults.stack)
+            .unwrap_or(StackLimit::Bytes(self.memory_bytes));
+
+        match stack {
+            StackLimit::Unlimited => limit(Resource::STACK, INFINITY),
+            StackLimit::Bytes(bytes) => limit(Resource::STACK, bytes),
+        }
+    }
+}
+
+/// Sets both the soft and hard limit, which cannot be raised above the current hard limit.
+fn limit(resource: Resource, value: u64) -> io::Result<()> {
+    let (_, hard) = getrlimit(resource)?;
+    let value = value.min(hard);
+    setrlimit(resource, value, value)
+}
+
+/// The CPU time used by a running (or exited but not yet reaped) process.
+pub fn cpu_time(pid: i32) -> io::Result<Duration> {
+    let mut clock = 0;
+
+    if unsafe { libc::clock_getcpuclockid(pid, &mut clock) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut timespec = std::mem::MaybeUninit::zeroed();
+
+    if unsafe { libc::clock_gettime(clock, timespec.as_mut_ptr()) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        let timespec = unsafe { timespec.assume_init() };
+        Ok(Duration::new(timespec.tv_sec, timespec.tv_nsec as i32))
+    }
+}
+
+/// Waits for a process to exit without reaping it, so that its process ID (and process group ID)
+/// cannot be reused yet.
+pub fn wait_exited(pid: i32) -> io::Result<()> {
+    let mut info = std::mem::MaybeUninit::<libc::siginfo_t>::zeroed();
+
+    loop {
+        let result = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid as libc::id_t,
+                info.as_mut_ptr(),
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+
+        if result == 0 {
+            return Ok(());
+        }
+
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
     }
 }
 
@@ -53,14 +229,14 @@ pub fn wait4(pid: i32) -> io::Result<(ExitStatus, ResourceUsage)> {
         Err(io::Error::last_os_error())
     } else {
         let rusage = unsafe { rusage.assume_init() };
-        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as i64;
 
         Ok((
             ExitStatus::from_raw(status),
             ResourceUsage {
                 user_time: timeval_to_duration(rusage.ru_utime),
                 sys_time: timeval_to_duration(rusage.ru_stime),
-                memory_bytes: (rusage.ru_maxrss * page_size / 2) as u64, // FIXME
+                // ru_maxrss is in kilobytes
+                memory_bytes: rusage.ru_maxrss as u64 * 1024,
             },
         ))
     }
@@ -71,3 +247,177 @@ fn timeval_to_duration(timeval: libc::timeval) -> Duration {
     let v = i64::from(timeval.tv_sec) * 1_000_000 + i64::from(timeval.tv_usec);
     Duration::microseconds(v)
 }
+
+mod units {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use time::Duration;
+
+    use super::StackLimit;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Quantity {
+        Number(f64),
+        Text(String),
+    }
+
+    pub fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        match Quantity::deserialize(deserializer)? {
+            Quantity::Number(seconds) if seconds >= 0.0 => Ok(Duration::seconds_f64(seconds)),
+            Quantity::Number(seconds) => {
+                Err(D::Error::custom(format!("invalid duration: {seconds}")))
+            }
+            Quantity::Text(text) => parse_duration(&text)
+                .ok_or_else(|| D::Error::custom(format!("invalid duration: {text}"))),
+        }
+    }
+
+    pub fn optional_duration<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        duration(deserializer).map(Some)
+    }
+
+    pub fn optional_bytes<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u64>, D::Error> {
+        bytes(deserializer).map(Some)
+    }
+
+    pub fn optional_stack<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<StackLimit>, D::Error> {
+        match Quantity::deserialize(deserializer)? {
+            Quantity::Text(text) if text == "unlimited" => Ok(Some(StackLimit::Unlimited)),
+            quantity => quantity_bytes(quantity).map(|bytes| Some(StackLimit::Bytes(bytes))),
+        }
+    }
+
+    // Written back in the same form, so that limits can be sent to remote workers
+
+    pub fn serialize_duration<S: Serializer>(
+        duration: &Duration,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{}us", duration.whole_microseconds()))
+    }
+
+    pub fn serialize_optional_duration<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serialize_duration(duration, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn serialize_optional_stack<S: Serializer>(
+        stack: &Option<StackLimit>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match stack {
+            Some(StackLimit::Unlimited) => serializer.serialize_str("unlimited"),
+            Some(StackLimit::Bytes(bytes)) => serializer.serialize_u64(*bytes),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn bytes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        quantity_bytes(Quantity::deserialize(deserializer)?)
+    }
+
+    fn quantity_bytes<E: Error>(quantity: Quantity) -> Result<u64, E> {
+        match quantity {
+            Quantity::Number(bytes) if bytes >= 0.0 => Ok(bytes as u64),
+            Quantity::Number(bytes) => Err(E::custom(format!("invalid size: {bytes}"))),
+            Quantity::Text(text) => {
+                parse_bytes(&text).ok_or_else(|| E::custom(format!("invalid size: {text}")))
+            }
+        }
+    }
+
+    fn split_unit(s: &str) -> Option<(f64, &str)> {
+        let s = s.trim();
+        let idx = s.find(|c: char| c.is_alphabetic()).unwrap_or(s.len());
+        let (value, unit) = s.split_at(idx);
+
+        let value: f64 = value.trim().parse().ok()?;
+        (value >= 0.0).then_some((value, unit))
+    }
+
+    pub(super) fn parse_duration(s: &str) -> Option<Duration> {
+        let (value, unit) = split_unit(s)?;
+
+        let seconds = match unit {
+            "us" | "µs" => value / 1_000_000.0,
+            "ms" => value / 1000.0,
+            "" | "s" => value,
+            "m" | "min" => value * 60.0,
+            _ => return None,
+        };
+
+        Some(Duration::seconds_f64(seconds))
+    }
+
+    pub(super) fn parse_bytes(s: &str) -> Option<u64> {
+        let (value, unit) = split_unit(s)?;
+
+        let multiplier: u64 = match unit {
+            "" | "B" => 1,
+            "KB" | "kB" => 1000,
+            "MB" => 1_000_000,
+            "GB" => 1_000_000_000,
+            "KiB" => 1 << 10,
+            "MiB" => 1 << 20,
+            "GiB" => 1 << 30,
+            _ => return None,
+        };
+
+        Some((value * multiplier as f64) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units() {
+        assert_eq!(
+            units::parse_duration("1500ms"),
+            Some(Duration::milliseconds(1500))
+        );
+        assert_eq!(
+            units::parse_duration("0.5s"),
+            Some(Duration::milliseconds(500))
+        );
+        assert_eq!(units::parse_duration("2"), Some(Duration::seconds(2)));
+        assert_eq!(units::parse_duration("1 hour"), None);
+
+        assert_eq!(units::parse_bytes("256MiB"), Some(256 << 20));
+        assert_eq!(units::parse_bytes("1.5 GB"), Some(1_500_000_000));
+        assert_eq!(units::parse_bytes("1000"), Some(1000));
+        assert_eq!(units::parse_bytes("-1MiB"), None);
+
+        let rlimits: ResourceLimits = serde_yaml::from_str("{ time: 1500ms, memory: 256MiB }")
+            .expect("failed to deserialize rlimits");
+        assert_eq!(rlimits.cpu_time, Duration::milliseconds(1500));
+        assert_eq!(rlimits.wall_time(), Duration::milliseconds(4500));
+        assert_eq!(rlimits.output_bytes, DEFAULT_OUTPUT_BYTES);
+        assert_eq!(rlimits.stack, None);
+
+        let rlimits: ResourceLimits =
+            serde_yaml::from_str("{ time: 1s, memory: 1GiB, stack: unlimited, file_size: 1MiB }")
+                .expect("failed to deserialize rlimits");
+        assert_eq!(rlimits.stack, Some(StackLimit::Unlimited));
+        assert_eq!(rlimits.file_size_bytes, Some(1 << 20));
+
+        let rlimits: ResourceLimits =
+            serde_yaml::from_str("{ cpu_seconds: 1, memory_bytes: 1000, wall_seconds: 2 }")
+                .expect("failed to deserialize legacy rlimits");
+        assert_eq!(rlimits.cpu_time, Duration::SECOND);
+        assert_eq!(rlimits.memory_bytes, 1000);
+        assert_eq!(rlimits.wall_time(), Duration::seconds(2));
+    }
+}
@@ -1,158 +1,594 @@
-use std::env::consts::ARCH;
+use std::{
+    collections::BTreeMap,
+    env::consts::ARCH,
+    io, mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    path::PathBuf,
+    ptr,
+    sync::{
+        mpsc::{self, Receiver, Sender, TryRecvError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
 
+pub use allowlist::{InvalidAllowlist, SyscallAllowlist};
 use libc::*;
 use once_cell::sync::Lazy;
+pub use opens::OpenCheck;
 use seccompiler::{
-    BpfProgram, Result, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition,
+    BackendError, BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition,
     SeccompFilter, SeccompRule,
 };
+use serde::Deserialize;
+
+use super::watchdog;
+use opens::Open;
+
+mod allowlist;
+mod opens;
+pub mod syscalls;
+
+// From linux/seccomp.h
+const SECCOMP_RET_USER_NOTIF: u32 = 0x7fc00000;
+const SECCOMP_IOCTL_NOTIF_RECV: u64 = 0xc0502100;
+const SECCOMP_IOCTL_NOTIF_SEND: u64 = 0xc0182101;
+
+/// Marks the returns of a compiled filter which are replaced with [`SECCOMP_RET_USER_NOTIF`],
+/// since seccompiler has no action for it
+const NOTIFY_MARKER: u32 = 0xfade;
+
+/// How often the notifier checks whether it has been stopped, in milliseconds
+const POLL_INTERVAL: c_int = 10;
+
+/// What happens when a submission makes a system call which is not allowed.
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SeccompMode {
+    /// The system call fails with `EPERM`, which the submission may handle or crash on
+    #[default]
+    Errno,
+    /// The system call fails with `EPERM` and is reported as a security violation
+    Trap,
+    /// The submission is killed and the system call is reported as a security violation
+    Kill,
+}
+
+/// The action for system calls which are not allowed, unless they are reported
+const DENY: SeccompAction = SeccompAction::Errno(EPERM as u32);
 
-static SECCOMP_FILTER: Lazy<BpfProgram> = Lazy::new(|| {
-    let filter = SeccompFilter::new(
-        [
-            (SYS_access, vec![]),
-            (SYS_arch_prctl, vec![]),
-            (SYS_brk, vec![]),
-            (SYS_clock_getres, vec![]),
-            (SYS_clock_gettime, vec![]),
-            (SYS_clone3, vec![]),
-            (SYS_clone, vec![]),
-            (SYS_close, vec![]),
-            (SYS_dup2, vec![]),
-            (SYS_dup3, vec![]),
-            (SYS_dup, vec![]),
-            (SYS_epoll_create1, vec![]),
-            (SYS_epoll_create, vec![]),
-            (SYS_epoll_ctl, vec![]),
-            (SYS_epoll_pwait, vec![]),
-            (SYS_epoll_wait, vec![]),
-            (SYS_execve, vec![]),
-            (SYS_exit_group, vec![]),
-            (SYS_exit, vec![]),
-            (SYS_fcntl, vec![]),
-            (SYS_fstat, vec![]),
-            (SYS_futex, vec![]),
-            (SYS_getcwd, vec![]),
-            (SYS_getdents64, vec![]),
-            (SYS_getdents, vec![]),
-            (SYS_getegid, vec![]),
-            (SYS_geteuid, vec![]),
-            (SYS_getgid, vec![]),
-            (SYS_getpgrp, vec![]),
-            (SYS_getpid, vec![]),
-            (SYS_getppid, vec![]),
-            (SYS_getrandom, vec![]),
-            (SYS_getrlimit, vec![]),
-            (SYS_getrusage, vec![]),
-            (SYS_gettid, vec![]),
-            (SYS_gettimeofday, vec![]),
-            (SYS_getuid, vec![]),
-            (SYS_ioctl, vec![]),
-            (SYS_lseek, vec![]),
-            (SYS_madvise, vec![]),
-            (SYS_mmap, vec![]),
-            (SYS_modify_ldt, vec![]),
-            (SYS_mprotect, vec![]),
-            (SYS_mremap, vec![]),
-            (SYS_munmap, vec![]),
-            (SYS_nanosleep, vec![]),
-            (SYS_newfstatat, vec![]),
-            (
-                SYS_open,
-                vec![
-                    SeccompRule::new(vec![SeccompCondition::new(
-                        1,
-                        SeccompCmpArgLen::Dword,
-                        SeccompCmpOp::Eq,
-                        O_RDONLY as u64,
-                    )
-                    .unwrap()])
-                    .unwrap(),
-                    SeccompRule::new(vec![SeccompCondition::new(
-                        1,
-                        SeccompCmpArgLen::Dword,
-                        SeccompCmpOp::Eq,
-                        (O_RDONLY | O_LARGEFILE | O_CLOEXEC) as u64,
-                    )
-                    .unwrap()])
-                    .unwrap(),
-                ],
-            ),
-            (
-                SYS_openat,
-                vec![
-                    SeccompRule::new(vec![SeccompCondition::new(
-                        2,
-                        SeccompCmpArgLen::Dword,
-                        SeccompCmpOp::Eq,
-                        O_RDONLY as u64,
-                    )
-                    .unwrap()])
-                    .unwrap(),
-                    SeccompRule::new(vec![SeccompCondition::new(
-                        2,
-                        SeccompCmpArgLen::Dword,
-                        SeccompCmpOp::Eq,
-                        (O_RDONLY | O_CLOEXEC) as u64,
-                    )
-                    .unwrap()])
-                    .unwrap(),
-                    SeccompRule::new(vec![SeccompCondition::new(
-                        2,
-                        SeccompCmpArgLen::Dword,
-                        SeccompCmpOp::Eq,
-                        (O_RDONLY | O_NONBLOCK | O_CLOEXEC | O_DIRECTORY) as u64,
-                    )
-                    .unwrap()])
-                    .unwrap(),
-                ],
-            ),
-            (SYS_pipe2, vec![]),
-            (SYS_pipe, vec![]),
-            (SYS_poll, vec![]),
-            (SYS_ppoll, vec![]),
-            (SYS_pread64, vec![]),
-            (SYS_readlinkat, vec![]),
-            (SYS_readlink, vec![]),
-            (SYS_read, vec![]),
-            (SYS_restart_syscall, vec![]),
-            (SYS_rseq, vec![]),
-            (SYS_rt_sigaction, vec![]),
-            (SYS_rt_sigprocmask, vec![]),
-            (SYS_rt_sigreturn, vec![]),
-            (SYS_sched_getaffinity, vec![]),
-            (SYS_sched_getparam, vec![]),
-            (SYS_sched_get_priority_max, vec![]),
-            (SYS_sched_get_priority_min, vec![]),
-            (SYS_sched_getscheduler, vec![]),
-            (SYS_sched_setscheduler, vec![]),
-            (SYS_sched_yield, vec![]),
-            (SYS_select, vec![]),
-            (SYS_set_robust_list, vec![]),
-            (SYS_set_thread_area, vec![]),
-            (SYS_set_tid_address, vec![]),
-            (SYS_sigaltstack, vec![]),
-            (SYS_statfs, vec![]),
-            (SYS_sysinfo, vec![]),
-            (SYS_timer_create, vec![]),
-            (SYS_timer_delete, vec![]),
-            (SYS_timerfd_create, vec![]),
-            (SYS_timer_settime, vec![]),
-            (SYS_time, vec![]),
-            (SYS_uname, vec![]),
-            (SYS_write, vec![]),
-            (SYS_writev, vec![]),
-        ]
-        .into(),
-        SeccompAction::Errno(EPERM as u32),
-        SeccompAction::Allow,
+/// Builds may not create sockets, including through io_uring, so that they have no network access.
+static BUILD_FILTER: Lazy<Arc<BpfProgram>> = Lazy::new(|| {
+    let denied = [(SYS_socket, vec![]), (SYS_io_uring_setup, vec![])];
+    let filter = compile(denied.into(), SeccompAction::Allow, DENY)
+        .expect("failed to compile seccomp filter");
+    Arc::new(filter)
+});
+
+/// The default allowlist, which languages may extend or replace.
+fn rules() -> BTreeMap<i64, Vec<SeccompRule>> {
+    [
+        (SYS_access, vec![]),
+        (SYS_arch_prctl, vec![]),
+        (SYS_brk, vec![]),
+        (SYS_clock_getres, vec![]),
+        (SYS_clock_gettime, vec![]),
+        (SYS_clone3, vec![]),
+        (SYS_clone, vec![]),
+        (SYS_close, vec![]),
+        (SYS_dup2, vec![]),
+        (SYS_dup3, vec![]),
+        (SYS_dup, vec![]),
+        (SYS_epoll_create1, vec![]),
+        (SYS_epoll_create, vec![]),
+        (SYS_epoll_ctl, vec![]),
+        (SYS_epoll_pwait, vec![]),
+        (SYS_epoll_wait, vec![]),
+        (SYS_execve, vec![]),
+        (SYS_exit_group, vec![]),
+        (SYS_exit, vec![]),
+        (SYS_fcntl, vec![]),
+        (SYS_fstat, vec![]),
+        (SYS_futex, vec![]),
+        (SYS_getcwd, vec![]),
+        (SYS_getdents64, vec![]),
+        (SYS_getdents, vec![]),
+        (SYS_getegid, vec![]),
+        (SYS_geteuid, vec![]),
+        (SYS_getgid, vec![]),
+        (SYS_getpgrp, vec![]),
+        (SYS_getpid, vec![]),
+        (SYS_getppid, vec![]),
+        (SYS_getrandom, vec![]),
+        (SYS_getrlimit, vec![]),
+        (SYS_getrusage, vec![]),
+        (SYS_gettid, vec![]),
+        (SYS_gettimeofday, vec![]),
+        (SYS_getuid, vec![]),
+        (SYS_ioctl, vec![]),
+        (SYS_lseek, vec![]),
+        (SYS_madvise, vec![]),
+        (SYS_mmap, vec![]),
+        (SYS_modify_ldt, vec![]),
+        (SYS_mprotect, vec![]),
+        (SYS_mremap, vec![]),
+        (SYS_munmap, vec![]),
+        (SYS_nanosleep, vec![]),
+        (SYS_newfstatat, vec![]),
+        (
+            SYS_open,
+            vec![
+                SeccompRule::new(vec![SeccompCondition::new(
+                    1,
+                    SeccompCmpArgLen::Dword,
+                    SeccompCmpOp::Eq,
+                    O_RDONLY as u64,
+                )
+                .unwrap()])
+                .unwrap(),
+                SeccompRule::new(vec![SeccompCondition::new(
+                    1,
+                    SeccompCmpArgLen::Dword,
+                    SeccompCmpOp::Eq,
+                    (O_RDONLY | O_LARGEFILE | O_CLOEXEC) as u64,
+                )
+                .unwrap()])
+                .unwrap(),
+            ],
+        ),
+        (
+            SYS_openat,
+            vec![
+                SeccompRule::new(vec![SeccompCondition::new(
+                    2,
+                    SeccompCmpArgLen::Dword,
+                    SeccompCmpOp::Eq,
+                    O_RDONLY as u64,
+                )
+                .unwrap()])
+                .unwrap(),
+                SeccompRule::new(vec![SeccompCondition::new(
+                    2,
+                    SeccompCmpArgLen::Dword,
+                    SeccompCmpOp::Eq,
+                    (O_RDONLY | O_CLOEXEC) as u64,
+                )
+                .unwrap()])
+                .unwrap(),
+                SeccompRule::new(vec![SeccompCondition::new(
+                    2,
+                    SeccompCmpArgLen::Dword,
+                    SeccompCmpOp::Eq,
+                    (O_RDONLY | O_NONBLOCK | O_CLOEXEC | O_DIRECTORY) as u64,
+                )
+                .unwrap()])
+                .unwrap(),
+            ],
+        ),
+        (SYS_pipe2, vec![]),
+        (SYS_pipe, vec![]),
+        (SYS_poll, vec![]),
+        (SYS_ppoll, vec![]),
+        (SYS_pread64, vec![]),
+        // glibc reads limits with prlimit64, which may only read those of the caller
+        (
+            SYS_prlimit64,
+            vec![SeccompRule::new(vec![
+                SeccompCondition::new(0, SeccompCmpArgLen::Dword, SeccompCmpOp::Eq, 0).unwrap(),
+                SeccompCondition::new(2, SeccompCmpArgLen::Qword, SeccompCmpOp::Eq, 0).unwrap(),
+            ])
+            .unwrap()],
+        ),
+        (SYS_readlinkat, vec![]),
+        (SYS_readlink, vec![]),
+        (SYS_read, vec![]),
+        (SYS_restart_syscall, vec![]),
+        (SYS_rseq, vec![]),
+        (SYS_rt_sigaction, vec![]),
+        (SYS_rt_sigprocmask, vec![]),
+        (SYS_rt_sigreturn, vec![]),
+        (SYS_sched_getaffinity, vec![]),
+        (SYS_sched_getparam, vec![]),
+        (SYS_sched_get_priority_max, vec![]),
+        (SYS_sched_get_priority_min, vec![]),
+        (SYS_sched_getscheduler, vec![]),
+        (SYS_sched_setscheduler, vec![]),
+        (SYS_sched_yield, vec![]),
+        (SYS_select, vec![]),
+        (SYS_set_robust_list, vec![]),
+        (SYS_set_thread_area, vec![]),
+        (SYS_set_tid_address, vec![]),
+        (SYS_sigaltstack, vec![]),
+        (SYS_statfs, vec![]),
+        (SYS_sysinfo, vec![]),
+        (SYS_timer_create, vec![]),
+        (SYS_timer_delete, vec![]),
+        (SYS_timerfd_create, vec![]),
+        (SYS_timer_settime, vec![]),
+        (SYS_time, vec![]),
+        (SYS_uname, vec![]),
+        (SYS_write, vec![]),
+        (SYS_writev, vec![]),
+    ]
+    .into()
+}
+
+fn compile(
+    rules: BTreeMap<i64, Vec<SeccompRule>>,
+    mismatch: SeccompAction,
+    matched: SeccompAction,
+) -> Result<BpfProgram, BackendError> {
+    SeccompFilter::new(
+        rules,
+        mismatch,
+        matched,
         ARCH.try_into().expect("unsupported architecture"),
+    )?
+    .try_into()
+}
+
+/// The filter for builds, which allows everything but network access.
+pub fn build_filter() -> Arc<BpfProgram> {
+    BUILD_FILTER.clone()
+}
+
+/// Runs a compiled filter on a system call in the judge, as the kernel would, returning its action.
+/// Only the instructions seccompiler generates are supported, anything else kills.
+fn evaluate(filter: &BpfProgram, data: &seccomp_data) -> u32 {
+    let mut bytes = Vec::with_capacity(mem::size_of::<seccomp_data>());
+    bytes.extend(data.nr.to_ne_bytes());
+    bytes.extend(data.arch.to_ne_bytes());
+    bytes.extend(data.instruction_pointer.to_ne_bytes());
+    for arg in data.args {
+        bytes.extend(arg.to_ne_bytes());
+    }
+
+    let (mut acc, mut pc) = (0u32, 0usize);
+    while let Some(instruction) = filter.get(pc) {
+        pc += 1;
+        let k = instruction.k;
+        let jump = |condition: bool| {
+            usize::from(if condition {
+                instruction.jt
+            } else {
+                instruction.jf
+            })
+        };
+
+        match u32::from(instruction.code) {
+            code if code == BPF_LD | BPF_W | BPF_ABS => {
+                let Some(word) = bytes.get(k as usize..k as usize + 4) else {
+                    break;
+                };
+                acc = u32::from_ne_bytes(word.try_into().unwrap());
+            }
+            code if code == BPF_ALU | BPF_AND | BPF_K => acc &= k,
+            code if code == BPF_JMP | BPF_JA => pc += k as usize,
+            code if code == BPF_JMP | BPF_JEQ | BPF_K => pc += jump(acc == k),
+            code if code == BPF_JMP | BPF_JGT | BPF_K => pc += jump(acc > k),
+            code if code == BPF_JMP | BPF_JGE | BPF_K => pc += jump(acc >= k),
+            code if code == BPF_RET | BPF_K => return k,
+            _ => break,
+        }
+    }
+
+    SECCOMP_RET_KILL_PROCESS
+}
+
+/// Installs a filter on a child process before it executes.
+pub fn apply_filter(filter: &BpfProgram) -> io::Result<()> {
+    seccompiler::apply_filter(filter).map_err(|e| io::Error::other(e.to_string()))
+}
+
+/// Compiles a filter which notifies a listener of system calls that are not allowed, unless the
+/// mode fails them anyway, and of every file opened if `check_opens` is set. The listener is sent
+/// over `socket_fd`, which is the only socket the filtered process may send a message on.
+pub fn notify_filter(
+    allowlist: &SyscallAllowlist,
+    socket_fd: RawFd,
+    mode: SeccompMode,
+    check_opens: bool,
+) -> BpfProgram {
+    let mut rules = allowlist.rules();
+    let send_listener = SeccompRule::new(vec![SeccompCondition::new(
+        0,
+        SeccompCmpArgLen::Dword,
+        SeccompCmpOp::Eq,
+        socket_fd as u64,
     )
-    .expect("failed to create seccomp filter");
+    .unwrap()])
+    .unwrap();
+    allowlist::allow(&mut rules, SYS_sendmsg, Some(send_listener));
 
-    filter.try_into().expect("failed to compile seccomp filter")
-});
+    let mismatch = match mode {
+        SeccompMode::Errno => DENY,
+        SeccompMode::Trap | SeccompMode::Kill => SeccompAction::Trace(NOTIFY_MARKER),
+    };
+
+    // The allowlist was already compiled when it was loaded
+    let mut filter =
+        compile(rules, mismatch, SeccompAction::Allow).expect("failed to compile seccomp filter");
+    for instruction in filter.iter_mut() {
+        if u32::from(instruction.code) == BPF_RET | BPF_K
+            && instruction.k == SECCOMP_RET_TRACE | NOTIFY_MARKER
+        {
+            instruction.k = SECCOMP_RET_USER_NOTIF;
+        }
+    }
+
+    if !check_opens {
+        return filter;
+    }
+
+    // Opens are notified before the allowlist, which the notifier evaluates itself
+    let instruction = |code, jt, jf, k| seccompiler::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    };
+    let mut opens = vec![
+        instruction(BPF_LD | BPF_W | BPF_ABS, 0, 0, 0),
+        instruction(BPF_JMP | BPF_JEQ | BPF_K, 1, 0, SYS_open as u32),
+        instruction(BPF_JMP | BPF_JEQ | BPF_K, 0, 1, SYS_openat as u32),
+        instruction(BPF_RET | BPF_K, 0, 0, SECCOMP_RET_USER_NOTIF),
+    ];
+    opens.append(&mut filter);
+    opens
+}
+
+/// Installs a filter from [`notify_filter`] on a child process before it executes, and sends the
+/// listener of the filter to the judge.
+pub fn apply_notify_filter(filter: &BpfProgram, socket_fd: RawFd) -> io::Result<()> {
+    let prog = sock_fprog {
+        len: filter.len() as c_ushort,
+        filter: filter.as_ptr() as *mut sock_filter,
+    };
+
+    let listener = unsafe {
+        if prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        syscall(
+            SYS_seccomp,
+            SECCOMP_SET_MODE_FILTER,
+            SECCOMP_FILTER_FLAG_NEW_LISTENER,
+            &prog as *const sock_fprog,
+        )
+    };
+
+    if listener < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let result = send_fd(socket_fd, listener as RawFd);
+    unsafe {
+        close(listener as RawFd);
+        close(socket_fd);
+    }
+    result
+}
+
+/// Space for a control message carrying one file descriptor, aligned for `cmsghdr`.
+#[repr(C)]
+union Control {
+    header: cmsghdr,
+    buf: [u8; unsafe { CMSG_SPACE(mem::size_of::<RawFd>() as c_uint) } as usize],
+}
+
+fn send_fd(socket_fd: RawFd, fd: RawFd) -> io::Result<()> {
+    unsafe {
+        let mut byte = 0u8;
+        let mut iov = iovec {
+            iov_base: ptr::addr_of_mut!(byte).cast(),
+            iov_len: 1,
+        };
+        let mut control: Control = mem::zeroed();
+        let mut msg: msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = ptr::addr_of_mut!(control).cast();
+        msg.msg_controllen = mem::size_of::<Control>();
+
+        let cmsg = CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = SOL_SOCKET;
+        (*cmsg).cmsg_type = SCM_RIGHTS;
+        (*cmsg).cmsg_len = CMSG_LEN(mem::size_of::<RawFd>() as c_uint) as usize;
+        ptr::write_unaligned(CMSG_DATA(cmsg).cast(), fd);
+
+        if sendmsg(socket_fd, &msg, 0) < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Receives the listener sent by [`apply_notify_filter`].
+pub fn receive_listener(socket: OwnedFd) -> io::Result<OwnedFd> {
+    unsafe {
+        let mut byte = 0u8;
+        let mut iov = iovec {
+            iov_base: ptr::addr_of_mut!(byte).cast(),
+            iov_len: 1,
+        };
+        let mut control: Control = mem::zeroed();
+        let mut msg: msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = ptr::addr_of_mut!(control).cast();
+        msg.msg_controllen = mem::size_of::<Control>();
+
+        if recvmsg(socket.as_raw_fd(), &mut msg, MSG_CMSG_CLOEXEC) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let cmsg = CMSG_FIRSTHDR(&msg);
+        if cmsg.is_null() || (*cmsg).cmsg_level != SOL_SOCKET || (*cmsg).cmsg_type != SCM_RIGHTS {
+            return Err(io::Error::other("no seccomp listener was received"));
+        }
+
+        let fd: RawFd = ptr::read_unaligned(CMSG_DATA(cmsg).cast());
+        Ok(OwnedFd::from_raw_fd(fd))
+    }
+}
+
+/// Creates a pair of connected sockets for sending the listener of a filter.
+pub fn socketpair() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+
+    if unsafe { libc::socketpair(AF_UNIX, SOCK_SEQPACKET | SOCK_CLOEXEC, 0, fds.as_mut_ptr()) } < 0
+    {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+    }
+}
+
+/// Handles the system calls a process is not allowed to make on another thread, recording them
+/// and failing them or killing the process group. Opens of files in protected directories are
+/// handled in the same way, if they are checked.
+#[derive(Debug)]
+pub struct Notifier {
+    cancel: Sender<()>,
+    handle: JoinHandle<Denied>,
+}
+
+/// What a process was denied, by system call number and by path.
+#[derive(Debug, Default)]
+pub struct Denied {
+    pub syscalls: Vec<i64>,
+    pub paths: Vec<PathBuf>,
+}
+
+impl Notifier {
+    pub fn start(
+        listener: OwnedFd,
+        mode: SeccompMode,
+        pgid: i32,
+        opens: Option<OpenCheck>,
+    ) -> Self {
+        let (cancel, cancelled) = mpsc::channel();
+        let handle = thread::spawn(move || notify(listener, mode, pgid, opens, cancelled));
+        Notifier { cancel, handle }
+    }
+
+    /// Stops handling system calls once the process has been reaped, returning what it was
+    /// denied.
+    pub fn stop(self) -> Denied {
+        self.cancel.send(()).ok();
+        self.handle.join().expect("seccomp notifier panicked")
+    }
+}
+
+fn notify(
+    listener: OwnedFd,
+    mode: SeccompMode,
+    pgid: i32,
+    opens: Option<OpenCheck>,
+    cancelled: Receiver<()>,
+) -> Denied {
+    let mut denied = Denied::default();
+
+    while let Err(TryRecvError::Empty) = cancelled.try_recv() {
+        let mut pollfd = pollfd {
+            fd: listener.as_raw_fd(),
+            events: POLLIN,
+            revents: 0,
+        };
+
+        if unsafe { poll(&mut pollfd, 1, POLL_INTERVAL) } <= 0 {
+            continue;
+        }
+
+        if pollfd.revents & POLLIN == 0 {
+            // Every process using the filter has exited
+            break;
+        }
+
+        let mut notification: seccomp_notif = unsafe { mem::zeroed() };
+        if unsafe {
+            ioctl(
+                listener.as_raw_fd(),
+                SECCOMP_IOCTL_NOTIF_RECV as _,
+                &mut notification,
+            )
+        } < 0
+        {
+            // The process was killed before the notification could be received
+            continue;
+        }
+
+        let data = notification.data;
+        if let Some(opens) = opens.as_ref().filter(|_| OpenCheck::handles(&data)) {
+            match opens.check(notification.pid, &data) {
+                Open::Allowed => {
+                    respond(
+                        &listener,
+                        notification.id,
+                        0,
+                        SECCOMP_USER_NOTIF_FLAG_CONTINUE,
+                    );
+                    continue;
+                }
+                Open::Denied(path) => {
+                    tracing::debug!("denied opening {}", path.display());
+                    if !denied.paths.contains(&path) {
+                        denied.paths.push(path);
+                    }
+
+                    match mode {
+                        SeccompMode::Kill => watchdog::kill_group(pgid),
+                        // As Landlock does
+                        SeccompMode::Errno | SeccompMode::Trap => {
+                            respond(&listener, notification.id, -EACCES, 0)
+                        }
+                    }
+                    continue;
+                }
+                // Not reported, like the system calls the filter fails itself
+                Open::Forbidden if mode == SeccompMode::Errno => {
+                    respond(&listener, notification.id, -EPERM, 0);
+                    continue;
+                }
+                Open::Forbidden => {}
+            }
+        }
+
+        let nr = i64::from(data.nr);
+        tracing::debug!(
+            "denied system call {} ({nr})",
+            syscalls::name(nr).unwrap_or("unknown")
+        );
+
+        if !denied.syscalls.contains(&nr) {
+            denied.syscalls.push(nr);
+        }
+
+        match mode {
+            SeccompMode::Kill => watchdog::kill_group(pgid),
+            SeccompMode::Errno | SeccompMode::Trap => {
+                respond(&listener, notification.id, -EPERM, 0)
+            }
+        }
+    }
+
+    denied
+}
 
-pub fn apply_filters() -> Result<()> {
-    seccompiler::apply_filter(&SECCOMP_FILTER)
+fn respond(listener: &OwnedFd, id: u64, error: c_int, flags: c_ulong) {
+    let mut response = seccomp_notif_resp {
+        id,
+        val: 0,
+        error,
+        flags: flags as u32,
+    };
+    unsafe {
+        ioctl(
+            listener.as_raw_fd(),
+            SECCOMP_IOCTL_NOTIF_SEND as _,
+            &mut response,
+        );
+    }
 }
@@ -0,0 +1,337 @@
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    fmt,
+    str::FromStr,
+    sync::Arc,
+};
+
+use libc::{seccomp_data, SYS_execve, SECCOMP_RET_ALLOW};
+use once_cell::sync::Lazy;
+use seccompiler::{
+    BackendError, BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition,
+    SeccompRule,
+};
+use serde::{
+    de::{self, value::MapAccessDeserializer, MapAccess},
+    Deserialize, Deserializer,
+};
+use serde_with::DeserializeFromStr;
+use thiserror::Error;
+
+use super::syscalls;
+
+static DEFAULT: Lazy<SyscallAllowlist> =
+    Lazy::new(|| SyscallAllowlist::new(super::rules()).expect("failed to compile seccomp filter"));
+
+/// The system calls that runs of a language may make, which extend or replace the default
+/// allowlist. The filter is compiled once, when the configuration is loaded.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "AllowlistConfig")]
+pub struct SyscallAllowlist {
+    rules: BTreeMap<i64, Vec<SeccompRule>>,
+    filter: Arc<BpfProgram>,
+}
+
+#[derive(Debug, Error)]
+pub enum InvalidAllowlist {
+    #[error("unknown system call: {0}")]
+    UnknownSyscall(String),
+    #[error("invalid argument condition for {0}: {1}")]
+    Condition(&'static str, BackendError),
+    #[error("masked-eq condition for {0} needs a mask")]
+    MissingMask(&'static str),
+    #[error("a replaced seccomp allowlist must allow execve")]
+    NoExecve,
+    #[error("failed to compile seccomp filter: {0}")]
+    Compile(#[from] BackendError),
+}
+
+impl SyscallAllowlist {
+    fn new(rules: BTreeMap<i64, Vec<SeccompRule>>) -> Result<Self, BackendError> {
+        let filter = super::compile(rules.clone(), super::DENY, SeccompAction::Allow)?;
+        Ok(SyscallAllowlist {
+            rules,
+            filter: Arc::new(filter),
+        })
+    }
+
+    /// The filter which fails system calls that are not allowed with `EPERM`.
+    pub fn filter(&self) -> Arc<BpfProgram> {
+        self.filter.clone()
+    }
+
+    pub(super) fn rules(&self) -> BTreeMap<i64, Vec<SeccompRule>> {
+        self.rules.clone()
+    }
+
+    /// Whether a system call is allowed, without making it.
+    pub(super) fn allows(&self, data: &seccomp_data) -> bool {
+        super::evaluate(&self.filter, data) == SECCOMP_RET_ALLOW
+    }
+}
+
+impl Default for SyscallAllowlist {
+    fn default() -> Self {
+        DEFAULT.clone()
+    }
+}
+
+/// Allows a system call, unconditionally if there is no rule. The rules for a system call are
+/// alternatives, so an unconditional rule overrides the others.
+pub(super) fn allow(
+    rules: &mut BTreeMap<i64, Vec<SeccompRule>>,
+    nr: i64,
+    rule: Option<SeccompRule>,
+) {
+    match rules.entry(nr) {
+        Entry::Vacant(entry) => {
+            entry.insert(rule.into_iter().collect());
+        }
+        Entry::Occupied(mut entry) => {
+            let rules = entry.get_mut();
+            match rule {
+                // No rules already allow it unconditionally
+                Some(rule) if !rules.is_empty() => rules.push(rule),
+                Some(_) => {}
+                None => rules.clear(),
+            }
+        }
+    }
+}
+
+/// e.g. `seccomp.extend = ["sched_getaffinity", { name = "prctl", args = [...] }]`
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum AllowlistConfig {
+    /// Allowed in addition to the default allowlist
+    Extend(Vec<AllowedSyscall>),
+    /// Allowed instead of the default allowlist
+    Replace(Vec<AllowedSyscall>),
+}
+
+impl TryFrom<AllowlistConfig> for SyscallAllowlist {
+    type Error = InvalidAllowlist;
+
+    fn try_from(config: AllowlistConfig) -> Result<Self, Self::Error> {
+        let (mut rules, allowed) = match config {
+            AllowlistConfig::Extend(allowed) => (super::rules(), allowed),
+            AllowlistConfig::Replace(allowed) => (BTreeMap::new(), allowed),
+        };
+
+        for AllowedSyscall { syscall, args } in allowed {
+            let rule = if args.is_empty() {
+                None
+            } else {
+                let conditions = args
+                    .into_iter()
+                    .map(|condition| condition.compile(syscall))
+                    .collect::<Result<_, _>>()?;
+
+                Some(
+                    SeccompRule::new(conditions)
+                        .map_err(|e| InvalidAllowlist::Condition(syscall.name(), e))?,
+                )
+            };
+
+            allow(&mut rules, syscall.0, rule);
+        }
+
+        if !rules.contains_key(&SYS_execve) {
+            return Err(InvalidAllowlist::NoExecve);
+        }
+
+        Ok(SyscallAllowlist::new(rules)?)
+    }
+}
+
+/// A system call given by name, which is allowed unconditionally, or if all of the conditions on
+/// its arguments hold when given as a table like `{ name = "openat", args = [...] }`.
+struct AllowedSyscall {
+    syscall: Syscall,
+    args: Vec<ArgCondition>,
+}
+
+impl<'de> Deserialize<'de> for AllowedSyscall {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Conditional {
+            name: Syscall,
+            args: Vec<ArgCondition>,
+        }
+
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = AllowedSyscall;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a system call name, or a table of its name and argument conditions")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<Self::Value, E> {
+                Ok(AllowedSyscall {
+                    syscall: name.parse().map_err(E::custom)?,
+                    args: Vec::new(),
+                })
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                let Conditional { name, args } =
+                    Conditional::deserialize(MapAccessDeserializer::new(map))?;
+                Ok(AllowedSyscall {
+                    syscall: name,
+                    args,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+#[derive(Debug, Clone, Copy, DeserializeFromStr)]
+struct Syscall(i64);
+
+impl Syscall {
+    fn name(&self) -> &'static str {
+        syscalls::name(self.0).expect("system call has no name")
+    }
+}
+
+impl FromStr for Syscall {
+    type Err = InvalidAllowlist;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        syscalls::number(s)
+            .map(Syscall)
+            .ok_or_else(|| InvalidAllowlist::UnknownSyscall(s.to_owned()))
+    }
+}
+
+/// A comparison of an argument, e.g. `{ arg = 2, op = "eq", value = 0 }` for `O_RDONLY` flags.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ArgCondition {
+    /// Index of the argument, from 0 to 5
+    arg: u8,
+    op: CmpOp,
+    value: u64,
+    /// The bits compared by `masked-eq`
+    #[serde(default)]
+    mask: Option<u64>,
+    #[serde(default)]
+    width: ArgWidth,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    MaskedEq,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ArgWidth {
+    /// Only the lower 32 bits, for `int` arguments such as flags
+    Dword,
+    #[default]
+    Qword,
+}
+
+impl ArgCondition {
+    fn compile(self, syscall: Syscall) -> Result<SeccompCondition, InvalidAllowlist> {
+        let op = match self.op {
+            CmpOp::Eq => SeccompCmpOp::Eq,
+            CmpOp::Ne => SeccompCmpOp::Ne,
+            CmpOp::Lt => SeccompCmpOp::Lt,
+            CmpOp::Le => SeccompCmpOp::Le,
+            CmpOp::Gt => SeccompCmpOp::Gt,
+            CmpOp::Ge => SeccompCmpOp::Ge,
+            CmpOp::MaskedEq => SeccompCmpOp::MaskedEq(
+                self.mask
+                    .ok_or(InvalidAllowlist::MissingMask(syscall.name()))?,
+            ),
+        };
+
+        let width = match self.width {
+            ArgWidth::Dword => SeccompCmpArgLen::Dword,
+            ArgWidth::Qword => SeccompCmpArgLen::Qword,
+        };
+
+        SeccompCondition::new(self.arg, width, op, self.value)
+            .map_err(|e| InvalidAllowlist::Condition(syscall.name(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Language {
+        seccomp: SyscallAllowlist,
+    }
+
+    fn parse(s: &str) -> Result<SyscallAllowlist, toml::de::Error> {
+        toml::from_str::<Language>(s).map(|language| language.seccomp)
+    }
+
+    #[test]
+    fn allowlists() {
+        let extended = parse(
+            r#"seccomp.extend = [
+                "socketpair",
+                { name = "openat", args = [{ arg = 2, op = "eq", value = 0o101, width = "dword" }] },
+            ]"#,
+        )
+        .unwrap();
+        let rules = extended.rules();
+        assert!(rules[&libc::SYS_socketpair].is_empty());
+        assert_eq!(rules[&libc::SYS_openat].len(), 4);
+        assert!(rules.contains_key(&libc::SYS_brk));
+
+        let replaced = parse(r#"seccomp.replace = ["execve", "exit_group"]"#).unwrap();
+        assert_eq!(replaced.rules().len(), 2);
+
+        let unknown = parse(r#"seccomp.extend = ["not_a_syscall"]"#).unwrap_err();
+        assert!(unknown
+            .message()
+            .contains("unknown system call: not_a_syscall"));
+
+        assert!(parse(r#"seccomp.replace = ["brk"]"#).is_err());
+        assert!(parse(
+            r#"seccomp.extend = [{ name = "ioctl", args = [{ arg = 6, op = "eq", value = 0 }] }]"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn evaluated_like_the_kernel() {
+        let allowlist = SyscallAllowlist::default();
+        let mut data: seccomp_data = unsafe { std::mem::zeroed() };
+        // AUDIT_ARCH_X86_64
+        data.arch = 0xc000003e;
+
+        data.nr = libc::SYS_openat as i32;
+        data.args[2] = libc::O_RDONLY as u64;
+        assert!(allowlist.allows(&data));
+        data.args[2] = (libc::O_WRONLY | libc::O_CREAT) as u64;
+        assert!(!allowlist.allows(&data));
+
+        data.nr = libc::SYS_socket as i32;
+        assert!(!allowlist.allows(&data));
+
+        // The same system call from 32-bit code, AUDIT_ARCH_I386
+        data.nr = libc::SYS_brk as i32;
+        assert!(allowlist.allows(&data));
+        data.arch = 0x40000003;
+        assert!(!allowlist.allows(&data));
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    collections::VecDeque,
+    ffi::{OsStr, OsString},
+    fs::{self, File},
+    os::unix::{ffi::OsStrExt, fs::FileExt},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use libc::{seccomp_data, SYS_open, SYS_openat, AT_FDCWD, PATH_MAX};
+
+use super::SyscallAllowlist;
+
+/// Symbolic links followed while resolving a path before giving up, as in the kernel
+const MAX_LINKS: usize = 40;
+
+/// Checks the files a run opens against directories it may not read, such as the test data of
+/// the task, so that trying to is reported rather than just failing.
+#[derive(Debug, Clone)]
+pub struct OpenCheck {
+    pub allowlist: SyscallAllowlist,
+    /// Canonical paths of the directories
+    pub protected: Arc<[PathBuf]>,
+}
+
+/// What became of an open which the check was notified of.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Open {
+    /// The allowlist does not allow the open, e.g. for writing
+    Forbidden,
+    /// The file is in a protected directory
+    Denied(PathBuf),
+    Allowed,
+}
+
+impl OpenCheck {
+    /// Whether a system call is one the check is notified of. Those of other architectures are
+    /// then forbidden by the allowlist.
+    pub fn handles(data: &seccomp_data) -> bool {
+        let nr = i64::from(data.nr);
+        nr == SYS_open || nr == SYS_openat
+    }
+
+    /// Checks an open by process `pid`. The path is read from the memory of the process, which
+    /// another of its threads could change afterwards, so this only reports what Landlock denies.
+    pub fn check(&self, pid: u32, data: &seccomp_data) -> Open {
+        if !self.allowlist.allows(data) {
+            return Open::Forbidden;
+        }
+
+        let (dirfd, path) = if i64::from(data.nr) == SYS_open {
+            (AT_FDCWD, data.args[0])
+        } else {
+            (data.args[0] as i32, data.args[1])
+        };
+
+        match read_path(pid, path).and_then(|path| resolve(pid, dirfd, &path)) {
+            Some(path) if self.protected.iter().any(|dir| path.starts_with(dir)) => {
+                Open::Denied(path)
+            }
+            // Opening a missing file fails anyway
+            _ => Open::Allowed,
+        }
+    }
+}
+
+fn read_path(pid: u32, address: u64) -> Option<PathBuf> {
+    let mem = File::open(format!("/proc/{pid}/mem")).ok()?;
+    let mut buf = vec![0; PATH_MAX as usize];
+    let len = mem.read_at(&mut buf, address).ok()?;
+
+    let end = buf[..len].iter().position(|&byte| byte == 0)?;
+    Some(PathBuf::from(OsStr::from_bytes(&buf[..end])))
+}
+
+/// Resolves a path opened by process `pid` relative to `dirfd`, as the process sees it. Returns
+/// `None` if it does not name an existing file, such as a link to a pipe.
+fn resolve(pid: u32, dirfd: i32, path: &Path) -> Option<PathBuf> {
+    let proc = PathBuf::from(format!("/proc/{pid}"));
+    let mut resolved = match dirfd {
+        AT_FDCWD => fs::read_link(proc.join("cwd")).ok()?,
+        fd => fs::read_link(proc.join("fd").join(fd.to_string())).ok()?,
+    };
+
+    let mut pending: VecDeque<OsString> = components(path).collect();
+    let mut links = 0;
+
+    while let Some(component) = pending.pop_front() {
+        match Path::new(&component).components().next()? {
+            Component::RootDir => resolved = PathBuf::from("/"),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                // As in the kernel, `missing/..` does not exist
+                if !resolved.is_dir() {
+                    return None;
+                }
+                resolved.pop();
+            }
+            Component::Normal(name) => {
+                // Links to the judge itself would be followed otherwise
+                let next = match name.to_str() {
+                    Some("self") if resolved == Path::new("/proc") => proc.clone(),
+                    Some("thread-self") if resolved == Path::new("/proc") => {
+                        proc.join("task").join(pid.to_string())
+                    }
+                    _ => resolved.join(name),
+                };
+
+                match fs::read_link(&next) {
+                    Ok(target) => {
+                        links += 1;
+                        if links > MAX_LINKS {
+                            return None;
+                        }
+
+                        for component in components(&target).rev() {
+                            pending.push_front(component);
+                        }
+                    }
+                    Err(_) => resolved = next,
+                }
+            }
+            Component::Prefix(_) => return None,
+        }
+    }
+
+    resolved.exists().then_some(resolved)
+}
+
+fn components(path: &Path) -> impl DoubleEndedIterator<Item = OsString> + '_ {
+    path.components()
+        .map(|component| component.as_os_str().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_like_the_process() {
+        let pid = std::process::id();
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().canonicalize().unwrap();
+        fs::write(dir.join("answer"), "").unwrap();
+        fs::create_dir(dir.join("sub")).unwrap();
+        std::os::unix::fs::symlink(dir.join("answer"), dir.join("link")).unwrap();
+
+        let resolve = |path: &Path| resolve(pid, AT_FDCWD, path);
+        assert_eq!(resolve(&dir.join("link")), Some(dir.join("answer")));
+        assert_eq!(
+            resolve(&dir.join("sub/../answer")),
+            Some(dir.join("answer"))
+        );
+        assert_eq!(resolve(&dir.join("missing/../answer")), None);
+        assert_eq!(resolve(&dir.join("missing")), None);
+        assert_eq!(
+            resolve(Path::new("/proc/self/cwd")),
+            Some(std::env::current_dir().unwrap())
+        );
+    }
+}
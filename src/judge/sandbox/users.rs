@@ -0,0 +1,163 @@
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    sync::{Condvar, Mutex},
+};
+
+use serde::Deserialize;
+
+// From linux/capability.h
+const CAP_CHOWN: u32 = 0;
+const CAP_SETGID: u32 = 6;
+const CAP_SETUID: u32 = 7;
+
+static LEASED: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+static RELEASED: Condvar = Condvar::new();
+
+/// A range of unprivileged users and groups to run sandboxed commands as. The first runs judge
+/// programs, the rest are leased to one submission each while it is judged.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserPool {
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct User {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl UserPool {
+    pub fn check(&self) -> io::Result<()> {
+        if self.size < 2 {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "user pool needs a user for judge programs and at least one for submissions",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether the judge can give sandbox directories to the users of the pool and run commands
+    /// as them, which needs root or its CAP_CHOWN, CAP_SETUID and CAP_SETGID capabilities.
+    pub fn permitted(&self) -> io::Result<bool> {
+        let status = fs::read_to_string("/proc/self/status")?;
+        let effective = status
+            .lines()
+            .find_map(|line| line.strip_prefix("CapEff:"))
+            .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+            .ok_or_else(|| io::Error::other("failed to read effective capabilities"))?;
+
+        Ok([CAP_CHOWN, CAP_SETUID, CAP_SETGID]
+            .iter()
+            .all(|cap| effective & (1 << cap) != 0))
+    }
+
+    /// The user which runs task-supplied judge programs.
+    pub fn judge(&self) -> User {
+        User {
+            uid: self.uid,
+            gid: self.gid,
+        }
+    }
+
+    /// Leases a user for a submission, waiting for one to be released if they are all in use.
+    pub fn lease(&self) -> Lease {
+        let mut leased = LEASED.lock().expect("failed to lock user pool");
+
+        loop {
+            let free = (1..self.size).find(|n| !leased.contains(&(self.uid + n)));
+
+            if let Some(n) = free {
+                leased.insert(self.uid + n);
+                return Lease(User {
+                    uid: self.uid + n,
+                    gid: self.gid + n,
+                });
+            }
+
+            tracing::debug!("waiting for a user to be released");
+            leased = RELEASED.wait(leased).expect("failed to lock user pool");
+        }
+    }
+}
+
+/// A user leased to a submission, which kills anything the submission left running as it before
+/// returning it to the pool.
+#[derive(Debug)]
+pub struct Lease(User);
+
+impl Lease {
+    pub fn user(&self) -> User {
+        self.0
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        if let Err(e) = kill_all(self.0) {
+            tracing::warn!("failed to kill processes of uid {}: {e}", self.0.uid);
+        }
+
+        LEASED
+            .lock()
+            .expect("failed to lock user pool")
+            .remove(&self.0.uid);
+        RELEASED.notify_one();
+    }
+}
+
+/// Kills every process of a user, from a child process running as the user.
+fn kill_all(user: User) -> io::Result<()> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => unsafe {
+            let code = if libc::setgid(user.gid) < 0 || libc::setuid(user.uid) < 0 {
+                1
+            } else {
+                // Every process the caller may signal, other than itself
+                libc::kill(-1, libc::SIGKILL);
+                0
+            };
+            libc::_exit(code)
+        },
+        pid => {
+            let mut status = 0;
+            if unsafe { libc::waitpid(pid, &mut status, 0) } < 0 {
+                Err(io::Error::last_os_error())
+            } else if libc::WEXITSTATUS(status) != 0 {
+                Err(io::Error::other("failed to switch user"))
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lease() {
+        let pool = UserPool {
+            uid: 30000,
+            gid: 40000,
+            size: 3,
+        };
+        let ids = |user: User| (user.uid, user.gid);
+        assert_eq!(ids(pool.judge()), (30000, 40000));
+
+        let first = pool.lease();
+        let second = pool.lease();
+        assert_eq!(ids(first.user()), (30001, 40001));
+        assert_eq!(ids(second.user()), (30002, 40002));
+
+        drop(first);
+        assert_eq!(ids(pool.lease().user()), (30001, 40001));
+    }
+}
@@ -0,0 +1,78 @@
+use std::{
+    sync::mpsc::{self, RecvTimeoutError},
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+use time::Duration;
+
+use super::{resource, Pids};
+
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// The limit a process was killed for exceeding.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Timeout {
+    CpuTime,
+    WallTime,
+}
+
+/// Polls the CPU time of sandboxed processes and kills their process groups once a CPU or
+/// wall-clock time limit is exceeded, so that processes which block (e.g. sleeping or waiting on a
+/// read) cannot hang the judge.
+#[derive(Debug)]
+pub struct Watchdog {
+    cancel: mpsc::Sender<()>,
+    handle: JoinHandle<Vec<Option<Timeout>>>,
+}
+
+impl Watchdog {
+    /// Watches processes with their CPU time limits.
+    pub fn start(processes: Vec<(Pids, Duration)>, wall_time: Duration) -> Self {
+        let (cancel, cancelled) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            let mut timeouts = vec![None; processes.len()];
+
+            loop {
+                match cancelled.recv_timeout(POLL_INTERVAL) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => return timeouts,
+                }
+
+                if start.elapsed() >= wall_time {
+                    timeouts.fill(Some(Timeout::WallTime));
+                } else {
+                    for (timeout, &(pids, cpu_time)) in timeouts.iter_mut().zip(processes.iter()) {
+                        if resource::cpu_time(pids.command).is_ok_and(|used| used > cpu_time) {
+                            *timeout = Some(Timeout::CpuTime);
+                        }
+                    }
+                }
+
+                if timeouts.iter().any(Option::is_some) {
+                    tracing::debug!("time limit exceeded, killing {processes:?}: {timeouts:?}");
+                    processes
+                        .iter()
+                        .for_each(|&(pids, _)| kill_group(pids.group));
+                    return timeouts;
+                }
+            }
+        });
+
+        Watchdog { cancel, handle }
+    }
+
+    /// Stops the watchdog, returning the limit each process was killed for exceeding.
+    pub fn stop(self) -> Vec<Option<Timeout>> {
+        self.cancel.send(()).ok();
+        self.handle.join().expect("watchdog thread panicked")
+    }
+}
+
+/// Kills every process in a process group. This must only be called while the group leader has
+/// not been reaped, otherwise the process group ID could have been reused.
+pub fn kill_group(pgid: i32) {
+    unsafe { libc::kill(-pgid, libc::SIGKILL) };
+}
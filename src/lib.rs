@@ -1,3 +1,4 @@
 pub mod contest;
 pub mod judge;
 pub mod web;
+pub mod worker;
@@ -1,7 +1,7 @@
 use std::env;
 
 use color_eyre::Result;
-use online_judge::web;
+use online_judge::{judge::remote::Token, web};
 use pico_args::Arguments;
 use tracing_subscriber::{prelude::*, EnvFilter};
 use tracing_tree::HierarchicalLayer;
@@ -21,6 +21,8 @@ OPTIONS:
   -C, --contest-dir   Set contest directory (contests)
   -s, --static-dir    Set static directory (static)
   -c, --config        Set judge config path (judge.toml)
+  -w, --workers       Set address to listen for remote judge workers on (judges locally if unset)
+  -t, --worker-token  Set shared secret expected from remote judge workers (needed unless -w is loopback)
 ";
 
 #[tokio::main]
@@ -59,6 +61,14 @@ async fn main() -> Result<()> {
                 .opt_value_from_str(["-c", "--config"])?
                 .unwrap_or_else(|| String::from("judge.toml"))
                 .into(),
+            worker_address: args
+                .opt_value_from_str::<_, String>(["-w", "--workers"])?
+                .or_else(|| env::var("WORKER_ADDRESS").ok())
+                .map(|address| address.parse())
+                .transpose()?,
+            worker_token: args
+                .opt_value_from_str(["-t", "--worker-token"])?
+                .or_else(|| env::var("WORKER_TOKEN").ok().map(Token::from)),
         };
 
         tracing::info!("starting server with config: {config:#?}");
@@ -13,18 +13,24 @@ use axum_login::{
     tower_sessions::{MemoryStore, SessionManagerLayer},
     AuthManagerLayerBuilder,
 };
+use color_eyre::Report;
 use tokio::{fs, net::TcpListener, sync::RwLock};
 use tower::ServiceBuilder;
 use tower_cookies::CookieManagerLayer;
 use tower_http::{compression::CompressionLayer, services::ServeDir, trace::TraceLayer};
 
-use self::database::Database;
-use crate::contest::Contest;
+use self::{database::Database, queue::Queue, remote::Workers};
+use crate::{
+    contest::Contest,
+    judge::{remote::Token, Config as JudgeConfig},
+};
 
 mod app;
 mod auth;
 mod database;
 mod error;
+mod queue;
+mod remote;
 mod session;
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
@@ -34,10 +40,23 @@ pub struct Config {
     pub contest_dir: PathBuf,
     pub static_dir: String,
     pub judge_config_path: PathBuf,
+    /// Where remote judge workers connect, submissions are judged locally otherwise
+    pub worker_address: Option<SocketAddr>,
+    /// The shared secret workers must give, if any
+    pub worker_token: Option<Token>,
 }
 
 #[tracing::instrument]
 pub async fn serve(config: Config) -> error::AppResult<()> {
+    if let Some(address) = config.worker_address {
+        if config.worker_token.is_none() && !address.ip().is_loopback() {
+            return Err(Report::msg(format!(
+                "judge workers need a token to listen on {address}, which is not a loopback address"
+            ))
+            .into());
+        }
+    }
+
     let db = Database::new(&config.database_url).await?;
     tracing::debug!("database loaded at {}", &config.database_url);
 
@@ -58,53 +77,85 @@ pub async fn serve(config: Config) -> error::AppResult<()> {
             .layer(AuthManagerLayerBuilder::new(backend, session_layer).build())
     };
 
-    let mut contest_paths = fs::read_dir(config.contest_dir).await?;
-    let mut contests = Vec::new();
-    while let Some(entry) = contest_paths.next_entry().await? {
-        let contest = tokio::task::spawn_blocking(move || Contest::load(entry.path())).await??;
-        contests.push(Arc::new(contest));
-    }
-    tracing::debug!("loaded {} contests", contests.len());
-
-    let judge_config = {
+    let judge_config: Arc<JudgeConfig> = {
         let judge_config_file = fs::read_to_string(&config.judge_config_path).await?;
         tracing::debug!(
             "loading judge config {}",
             config.judge_config_path.display()
         );
-        toml::from_str(&judge_config_file)?
+        let mut judge_config: JudgeConfig = toml::from_str(&judge_config_file)?;
+        judge_config.check_users()?;
+        judge_config.check_cgroup();
+        judge_config.check_namespaces();
+        // Remote workers are only sent the tests themselves
+        if config.worker_address.is_none() {
+            judge_config.check_protection()?;
+        }
+        Arc::new(judge_config)
     };
 
-    let app = app::router(app::App {
+    let mut contest_paths = fs::read_dir(config.contest_dir).await?;
+    let mut contests = Vec::new();
+    while let Some(entry) = contest_paths.next_entry().await? {
+        let judge_config = judge_config.clone();
+        let contest =
+            tokio::task::spawn_blocking(move || Contest::load(entry.path(), &judge_config))
+                .await??;
+        contests.push(Arc::new(contest));
+    }
+    tracing::debug!("loaded {} contests", contests.len());
+
+    let app = app::App {
         db,
         contests,
         sessions: Arc::new(RwLock::new(HashMap::new())),
         judge_config,
-    })
-    .merge(auth::router())
-    .nest_service("/static", ServeDir::new(config.static_dir))
-    .layer(
-        ServiceBuilder::new()
-            .layer(TraceLayer::new_for_http())
-            .layer(CompressionLayer::new())
-            .layer(from_fn(|request: Request, next: Next| async {
-                #[derive(Template)]
-                #[template(path = "not_found.html")]
-                struct NotFound;
-
-                let htmx = request.headers().contains_key("HX-Request");
-
-                let mut response = next.run(request).await;
-                if response.status() == StatusCode::NOT_FOUND && !htmx {
-                    *response.body_mut() =
-                        NotFound.render().expect("failed to render template").into();
-                }
-
-                response
-            }))
-            .layer(CookieManagerLayer::new())
-            .layer(auth_service),
-    );
+        queue: Queue::new(),
+        workers: config
+            .worker_address
+            .map(|_| Workers::new(config.worker_token.clone())),
+    };
+
+    if let (Some(workers), Some(address)) = (app.workers.clone(), config.worker_address) {
+        let listener = TcpListener::bind(address).await?;
+        tokio::spawn(async move {
+            if let Err(e) = workers.listen(listener).await {
+                tracing::error!("stopped listening for judge workers: {e}");
+            }
+        });
+    }
+
+    let requeued = app.queue.requeue(&app.db).await?;
+    if requeued > 0 {
+        tracing::info!("requeued {requeued} pending submissions");
+    }
+    queue::spawn_workers(&app);
+
+    let app = app::router(app)
+        .merge(auth::router())
+        .nest_service("/static", ServeDir::new(config.static_dir))
+        .layer(
+            ServiceBuilder::new()
+                .layer(TraceLayer::new_for_http())
+                .layer(CompressionLayer::new())
+                .layer(from_fn(|request: Request, next: Next| async {
+                    #[derive(Template)]
+                    #[template(path = "not_found.html")]
+                    struct NotFound;
+
+                    let htmx = request.headers().contains_key("HX-Request");
+
+                    let mut response = next.run(request).await;
+                    if response.status() == StatusCode::NOT_FOUND && !htmx {
+                        *response.body_mut() =
+                            NotFound.render().expect("failed to render template").into();
+                    }
+
+                    response
+                }))
+                .layer(CookieManagerLayer::new())
+                .layer(auth_service),
+        );
 
     let listener = TcpListener::bind(config.server_address).await?;
     tracing::info!("listening on http://{}", listener.local_addr().unwrap());
@@ -6,7 +6,7 @@ use axum::{
     http::StatusCode,
     middleware::map_response_with_state,
     response::{sse::*, Response},
-    routing::get,
+    routing::{get, post},
     Router,
 };
 use axum_login::{login_required, AuthzBackend};
@@ -19,6 +19,8 @@ use super::{
     auth::{AuthSession, Backend, Permissions, User},
     database::Database,
     error::{AppError, AppResult},
+    queue::Queue,
+    remote::Workers,
     session::Session,
 };
 use crate::{contest::*, judge::Config as JudgeConfig};
@@ -34,6 +36,9 @@ pub struct App {
     pub contests: Vec<Arc<Contest>>,
     pub sessions: Arc<RwLock<HashMap<i64, Arc<Session>>>>,
     pub judge_config: Arc<JudgeConfig>,
+    pub queue: Queue,
+    /// Judge submissions remotely instead of locally, if any
+    pub workers: Option<Workers>,
 }
 
 pub fn router(app: App) -> Router {
@@ -74,6 +79,9 @@ pub fn router(app: App) -> Router {
 
         Router::new()
             .route("/submit/:task_id", get(submissions).post(submit))
+            .route("/submit/:task_id/outputs", post(submit_outputs))
+            .route("/submit/:task_id/progress", get(submission_progress))
+            .route("/submit/:task_id/sse", get(submission_sse))
             .route("/task/:task_id", get(task))
             .route_layer(login_required!(Backend, login_url = "/login"))
             .route("/leaderboard", get(leaderboard))
@@ -102,6 +110,12 @@ struct ContestNavigation {
     task_id: i64,
 }
 
+impl ContestNavigation {
+    fn submit_url(&self) -> String {
+        format!("/contest/{}/submit/{}", self.session_id, self.task_id)
+    }
+}
+
 #[derive(Template)]
 #[template(path = "index.html")]
 struct IndexPage;
@@ -114,13 +128,7 @@ struct Sessions {
 
 async fn sessions(State(app): State<App>) -> Sessions {
     Sessions {
-        sessions: app
-            .sessions
-            .read()
-            .await
-            .iter()
-            .map(|(_, session)| session.clone())
-            .collect(),
+        sessions: app.sessions.read().await.values().cloned().collect(),
     }
 }
 
@@ -5,15 +5,17 @@ use axum::{
     extract::{Extension, Query, State},
     http::StatusCode,
     response::Response,
-    routing::get,
+    routing::{get, post},
     Router,
 };
 use axum_login::{permission_required, AuthzBackend};
 use serde::Deserialize;
+use time::OffsetDateTime;
 use tokio::sync::watch::Sender;
 use tokio_stream::StreamExt;
 
 use crate::contest::Contest;
+use crate::judge::Verdict;
 use crate::web::{
     app::App,
     auth::{AuthSession, Backend, Permissions, User},
@@ -25,8 +27,11 @@ pub fn router(app: App, tx: Arc<Sender<()>>) -> Router {
     Router::new()
         .route("/admin", get(move || async { AdminPage }))
         .route("/admin/sessions", get(sessions).post(sessions_action))
+        .route("/admin/sessions/retry", post(retry_judge_errors))
         .route("/admin/contests", get(contests).put(create_session))
         .route("/admin/users", get(users).delete(delete_user))
+        .route("/admin/violations", get(violations))
+        .route("/admin/judge-errors", get(judge_errors))
         .route_layer(permission_required!(Backend, Permissions::ADMIN))
         .layer(Extension(tx))
         .with_state(app)
@@ -137,6 +142,24 @@ async fn sessions_action(
     })
 }
 
+#[derive(Debug, Deserialize)]
+struct SessionId {
+    id: i64,
+}
+
+/// Judges the submissions to a session which were given a judge error again.
+async fn retry_judge_errors(
+    State(app): State<App>,
+    Query(SessionId { id }): Query<SessionId>,
+) -> AppResult<Response> {
+    let count = app.queue.retry(&app.db, id).await?;
+    tracing::info!("requeued {count} submissions with judge errors in session {id}");
+
+    Ok(Response::builder()
+        .header("HX-Trigger", "reloadJudgeErrors")
+        .body(format!("Requeued {count} submissions").into())?)
+}
+
 #[derive(Template)]
 #[template(path = "admin/contest_table.html")]
 struct ContestTable {
@@ -256,3 +279,98 @@ async fn delete_user(
         Ok(StatusCode::OK)
     }
 }
+
+/// A submission given a verdict which needs looking into, with the judge error or the message of
+/// its first test with the verdict.
+struct FlaggedSubmission {
+    submission_id: i64,
+    username: String,
+    session_id: i64,
+    task: i64,
+    datetime: OffsetDateTime,
+    language: String,
+    detail: Option<String>,
+}
+
+/// A page of the submissions with a verdict, loaded from `/admin/{name}`.
+#[derive(Template)]
+#[template(path = "admin/verdict_table.html")]
+struct VerdictTable {
+    name: &'static str,
+    empty: &'static str,
+    page: usize,
+    submissions: Vec<FlaggedSubmission>,
+    more: bool,
+}
+
+impl VerdictTable {
+    async fn load(
+        app: &App,
+        verdict: Verdict,
+        (name, empty): (&'static str, &'static str),
+        page: usize,
+    ) -> AppResult<Self> {
+        let offset = 10 * (page - 1) as i64;
+        let verdict = verdict.to_string();
+
+        let submissions = sqlx::query!(
+            r#"SELECT submissions.id, users.username, submissions.session_id, submissions.task,
+                submissions.datetime, submissions.language,
+                COALESCE(submissions.judge_error,
+                    (SELECT tests.message FROM tests JOIN subtasks ON tests.subtask_id = subtasks.id
+                    WHERE subtasks.submission_id = submissions.id AND tests.verdict = ?1
+                    ORDER BY subtasks.subtask, tests.test LIMIT 1)) AS "detail?"
+            FROM submissions JOIN users ON submissions.user_id = users.id
+            WHERE submissions.verdict = ?1
+            ORDER BY submissions.datetime DESC LIMIT 10 OFFSET ?2;"#,
+            verdict,
+            offset
+        )
+        .fetch(app.db.pool())
+        .map(|res| {
+            res.map(|submission| FlaggedSubmission {
+                submission_id: submission.id,
+                username: submission.username,
+                session_id: submission.session_id,
+                task: submission.task,
+                datetime: submission.datetime,
+                language: submission.language,
+                detail: submission.detail,
+            })
+        })
+        .collect::<Result<_, _>>()
+        .await?;
+
+        let count = sqlx::query!(
+            "SELECT COUNT(*) AS count FROM submissions WHERE verdict = ?;",
+            verdict
+        )
+        .fetch_one(app.db.pool())
+        .await?
+        .count as usize;
+
+        Ok(VerdictTable {
+            name,
+            empty,
+            page,
+            submissions,
+            more: count > page * 10,
+        })
+    }
+}
+
+async fn violations(
+    State(app): State<App>,
+    Query(Pagination { page }): Query<Pagination>,
+) -> AppResult<VerdictTable> {
+    let names = ("violations", "No security violations");
+    VerdictTable::load(&app, Verdict::SecurityViolation, names, page).await
+}
+
+async fn judge_errors(
+    State(app): State<App>,
+    Query(Pagination { page }): Query<Pagination>,
+) -> AppResult<VerdictTable> {
+    let names = ("judge-errors", "No judge errors");
+    VerdictTable::load(&app, Verdict::JudgeError, names, page).await
+}
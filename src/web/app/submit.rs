@@ -1,25 +1,34 @@
-use std::sync::Arc;
+use std::{pin::Pin, sync::Arc};
 
 use askama::Template;
 use axum::{
+    body::Bytes,
     extract::{Path, State},
     http::StatusCode,
-    response::Redirect,
+    response::{sse::*, Redirect},
 };
 
-use axum_typed_multipart::TypedMultipart;
+use axum_typed_multipart::{FieldData, TryFromMultipart, TypedMultipart};
 
 use time::OffsetDateTime;
-use tokio_stream::StreamExt;
+use tokio_stream::{wrappers::WatchStream, Stream, StreamExt};
 use tower_cookies::{Cookie, Cookies};
 
 use super::{App, ContestNavigation};
 use crate::{
-    judge::{GradedTask, JudgeError, Language, Submission, Verdict},
-    web::{auth::AuthSession, error::*, session::UserTask},
+    contest::TaskKind,
+    judge::{self, Language, Submission, Verdict},
+    web::{
+        auth::AuthSession,
+        error::*,
+        queue::{Judging, Status},
+        session::UserTask,
+    },
 };
 
 const LANGUAGE_COOKIE: &str = "preferred-language";
+/// Recorded as the language of output-only submissions
+const OUTPUT_ONLY_LANGUAGE: &str = "Output only";
 
 #[derive(Template)]
 #[template(path = "contest/submit.html")]
@@ -33,6 +42,7 @@ pub struct SubmitPage {
     languages: Vec<Language>,
     preferred_language: Option<String>,
     accept: String,
+    output_only: bool,
 
     // Submission results
     reports: Vec<TaskReport>,
@@ -51,6 +61,18 @@ pub struct TaskReport {
 pub struct SubtaskReport {
     scores: Vec<(Verdict, u32, u32)>,
     overall: (Verdict, u32, u32),
+    tests: Vec<(usize, TestReport)>,
+}
+
+pub struct TestReport {
+    test: i64,
+    verdict: Verdict,
+    score: f64,
+    time: Option<i64>,
+    memory: Option<i64>,
+    message: Option<String>,
+    /// How a crashed process exited
+    exit: Option<String>,
 }
 
 pub async fn submissions(
@@ -83,7 +105,21 @@ pub async fn submissions(
             (elapsed < contest_cooldown).then_some((contest_cooldown - elapsed).whole_seconds())
         });
 
-    let languages = app.judge_config.languages.clone();
+    let task = session
+        .contest
+        .tasks
+        .get(task_id as usize - 1)
+        .ok_or(AppError::StatusCode(StatusCode::NOT_FOUND))?;
+
+    let output_only = task.kind == TaskKind::OutputOnly;
+
+    let languages: Vec<_> = app
+        .judge_config
+        .languages
+        .iter()
+        .filter(|language| task.supports(language))
+        .cloned()
+        .collect();
 
     let preferred_language = cookies
         .get(LANGUAGE_COOKIE)
@@ -113,39 +149,59 @@ pub async fn submissions(
             subtask_report: SubtaskReport {
                 scores: Vec::new(),
                 overall: (Verdict::Accepted, 0, 0),
+                tests: Vec::new(),
             },
         })
     })
     .collect::<Result<_, _>>()
     .await?;
 
+    let last_submission_id = reports.last().map(|report| report.submission_id);
+
     for report in reports.iter_mut() {
-        let mut stream = sqlx::query!(
+        let subtasks: Vec<_> = sqlx::query!(
             "SELECT * FROM subtasks WHERE submission_id = ? ORDER BY subtask;",
             report.submission_id
         )
-        .fetch(app.db.pool())
-        .map(|res| {
-            res.map(|score| {
-                (
-                    score.verdict.parse().expect("invalid verdict"),
-                    score.score as u32,
-                )
-            })
-        });
+        .fetch_all(app.db.pool())
+        .await?;
+
+        let subtask_report = &mut report.subtask_report;
+        let (overall_verdict, overall_score, overall_max) = &mut subtask_report.overall;
 
-        let scores = &mut report.subtask_report.scores;
-        let (overall_verdict, overall_score, overall_max) = &mut report.subtask_report.overall;
+        for (idx, subtask) in subtasks.into_iter().enumerate() {
+            let verdict = subtask.verdict.parse().expect("invalid verdict");
+            let score = subtask.score as u32;
 
-        let mut idx = 0;
-        while let Some((verdict, score)) = stream.try_next().await? {
-            // NOTE: this works on the assumption that 1 point is awarded for each correct test
-            let max = session.contest.tasks[task_id as usize - 1].subtasks[idx].tests as u32;
-            scores.push((verdict, score, max));
+            let max = session.contest.tasks[task_id as usize - 1].subtasks[idx].points;
+            subtask_report.scores.push((verdict, score, max));
             *overall_verdict = (*overall_verdict).min(verdict);
             *overall_score += score;
             *overall_max += max;
-            idx += 1;
+
+            // Test details are only shown for the latest submission
+            if Some(report.submission_id) == last_submission_id {
+                let mut stream = sqlx::query!(
+                    "SELECT * FROM tests WHERE subtask_id = ? ORDER BY test;",
+                    subtask.id
+                )
+                .fetch(app.db.pool());
+
+                while let Some(test) = stream.try_next().await? {
+                    subtask_report.tests.push((
+                        idx + 1,
+                        TestReport {
+                            test: test.test,
+                            verdict: test.verdict.parse().expect("invalid verdict"),
+                            score: test.score,
+                            time: test.time,
+                            memory: test.memory,
+                            message: test.message,
+                            exit: describe_exit(test.exit_code, test.signal),
+                        },
+                    ));
+                }
+            }
         }
     }
 
@@ -173,162 +229,272 @@ pub async fn submissions(
         languages,
         preferred_language,
         accept,
+        output_only,
 
         reports,
         overall,
     })
 }
 
-#[tracing::instrument(skip(auth_session, app))]
-pub async fn submit(
+#[derive(Template)]
+#[template(path = "contest/submit_progress.html")]
+pub struct ProgressFragment {
+    judging: Judging,
+}
+
+/// Fails with not found unless the session has the task.
+async fn check_task(app: &App, session_id: i64, task_id: i64) -> AppResult<()> {
+    let sessions = app.sessions.read().await;
+    let task_count = sessions
+        .get(&session_id)
+        .map(|session| session.contest.tasks.len())
+        .ok_or(AppError::StatusCode(StatusCode::NOT_FOUND))?;
+
+    if (1..=task_count as i64).contains(&task_id) {
+        Ok(())
+    } else {
+        Err(AppError::StatusCode(StatusCode::NOT_FOUND))
+    }
+}
+
+/// The progress of judging the latest submission of the user to the task, if it is being judged.
+pub async fn submission_progress(
+    auth_session: AuthSession,
+    State(app): State<App>,
+    Path(ContestNavigation {
+        session_id,
+        task_id,
+    }): Path<ContestNavigation>,
+) -> AppResult<ProgressFragment> {
+    let user_id = auth_session
+        .user
+        .map(|user| user.id())
+        .ok_or(AppError::StatusCode(StatusCode::UNAUTHORIZED))?;
+
+    check_task(&app, session_id, task_id).await?;
+
+    let judging = app
+        .queue
+        .subscribe((session_id, user_id, task_id))
+        .ok_or(AppError::StatusCode(StatusCode::NOT_FOUND))?
+        .borrow()
+        .clone();
+
+    Ok(ProgressFragment { judging })
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<Event, axum::Error>> + Send>>;
+
+/// Sends a `progress` event whenever the latest submission of the user to the task progresses,
+/// and a `judged` event once it has been judged, right away if it is not being judged.
+pub async fn submission_sse(
     auth_session: AuthSession,
-    cookies: Cookies,
     State(app): State<App>,
     Path(ContestNavigation {
         session_id,
         task_id,
     }): Path<ContestNavigation>,
+) -> AppResult<Sse<impl Stream<Item = Result<Event, axum::Error>>>> {
+    let user_id = auth_session
+        .user
+        .map(|user| user.id())
+        .ok_or(AppError::StatusCode(StatusCode::UNAUTHORIZED))?;
+
+    check_task(&app, session_id, task_id).await?;
+
+    // The submission may have been judged and forgotten before the page connected
+    let events: EventStream = match app.queue.subscribe((session_id, user_id, task_id)) {
+        Some(rx) => Box::pin(WatchStream::new(rx).map(|judging| {
+            let judged = judging.status == Status::Judged;
+            Ok(Event::default().event(if judged { "judged" } else { "progress" }))
+        })),
+        None => Box::pin(tokio_stream::once(Ok(Event::default().event("judged")))),
+    };
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::new()))
+}
+
+fn describe_exit(exit_code: Option<i64>, signal: Option<i64>) -> Option<String> {
+    match (exit_code, signal) {
+        (_, Some(signal)) => Some(match judge::signal_name(signal as i32) {
+            Some((name, description)) => format!("{name} ({description})"),
+            None => format!("signal {signal}"),
+        }),
+        (Some(code), None) => Some(format!("exit code {code}")),
+        (None, None) => None,
+    }
+}
+
+#[tracing::instrument(skip(auth_session, cookies, app))]
+pub async fn submit(
+    auth_session: AuthSession,
+    cookies: Cookies,
+    State(app): State<App>,
+    Path(navigation): Path<ContestNavigation>,
     TypedMultipart(submission): TypedMultipart<Submission>,
 ) -> AppResult<Redirect> {
+    let redirect_url = navigation.submit_url();
+    let language = submission.language.clone();
+
+    let queued = enqueue(
+        auth_session,
+        &app,
+        navigation,
+        (submission.code, submission.language),
+        None,
+    )
+    .await?;
+
+    if queued {
+        cookies.add(Cookie::new(LANGUAGE_COOKIE, language));
+    }
+
+    Ok(Redirect::to(&redirect_url))
+}
+
+#[derive(Debug, TryFromMultipart)]
+pub struct OutputSubmission {
+    outputs: Vec<FieldData<Bytes>>,
+}
+
+#[tracing::instrument(skip(auth_session, app, submission))]
+pub async fn submit_outputs(
+    auth_session: AuthSession,
+    State(app): State<App>,
+    Path(navigation): Path<ContestNavigation>,
+    TypedMultipart(submission): TypedMultipart<OutputSubmission>,
+) -> AppResult<Redirect> {
+    let redirect_url = navigation.submit_url();
+
+    let files: Vec<_> = submission
+        .outputs
+        .into_iter()
+        .filter_map(|file| Some((file.metadata.file_name?, file.contents.to_vec())))
+        .collect();
+
+    // Only the names of output files are recorded as the submission code, their contents are
+    // kept until they are judged
+    let filenames = files
+        .iter()
+        .map(|(filename, _)| filename.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    enqueue(
+        auth_session,
+        &app,
+        navigation,
+        (filenames, String::from(OUTPUT_ONLY_LANGUAGE)),
+        Some(files),
+    )
+    .await?;
+
+    Ok(Redirect::to(&redirect_url))
+}
+
+/// Records a submission as pending and queues it to be judged, unless the contest has ended or
+/// the user is on cooldown. Output-only submissions come with their output files. Returns
+/// whether the submission was queued.
+async fn enqueue(
+    auth_session: AuthSession,
+    app: &App,
+    ContestNavigation {
+        session_id,
+        task_id,
+    }: ContestNavigation,
+    (code, language): (String, String),
+    outputs: Option<Vec<(String, Vec<u8>)>>,
+) -> AppResult<bool> {
     let user = auth_session
         .user
         .ok_or(AppError::StatusCode(StatusCode::UNAUTHORIZED))?;
     let user_id = user.id();
 
-    let redirect_url = format!("/contest/{session_id}/submit/{task_id}");
-
     let now = OffsetDateTime::now_utc();
 
-    let judge_result = {
-        let session = app
-            .sessions
-            .read()
-            .await
+    {
+        let sessions = app.sessions.read().await;
+        let session = sessions
             .get(&session_id)
-            .cloned()
             .ok_or(AppError::StatusCode(StatusCode::NOT_FOUND))?;
 
         if session.end.is_some() {
-            return Ok(Redirect::to(&redirect_url));
+            return Ok(false);
         }
 
         if let Some(previous) = session.users.get(&(user_id, task_id)) {
             if now - previous.cooldown < session.contest.cooldown {
                 tracing::trace!("user (ID: {user_id}) attempted to submit but was on cooldown");
-                return Ok(Redirect::to(&redirect_url));
+                return Ok(false);
             }
         }
 
-        tracing::trace!("received submission from user (ID: {user_id}) for task {task_id} of contest session {session_id}");
-
-        let config = app.judge_config.clone();
-        let submission = submission.clone();
         let task = session
             .contest
             .tasks
             .get(task_id as usize - 1)
-            .cloned()
             .ok_or(AppError::StatusCode(StatusCode::NOT_FOUND))?;
 
-        tokio::task::spawn_blocking(move || {
-            use crate::judge;
-
-            let verdicts = judge::run(&config, submission, &task, session.contest.rlimits)?;
-            let grade = judge::grade(&task, &verdicts);
+        let supported = match outputs {
+            Some(_) => task.kind == TaskKind::OutputOnly,
+            None => {
+                task.kind != TaskKind::OutputOnly
+                    && app
+                        .judge_config
+                        .language(&language)
+                        .is_ok_and(|language| task.supports(language))
+            }
+        };
 
-            Ok::<_, JudgeError>(grade)
-        })
-        .await?
-    };
+        if !supported {
+            return Err(AppError::StatusCode(StatusCode::BAD_REQUEST));
+        }
+    }
 
-    let (grade, compile_error) = match judge_result {
-        Ok(grade) => (grade, None),
-        Err(JudgeError::CompileError(stderr)) => (
-            GradedTask {
-                verdict: Verdict::CompileError,
-                score: 0,
-                subtasks: Vec::new(),
-            },
-            Some(stderr),
-        ),
-        Err(e) => return Err(e.into()),
-    };
+    tracing::trace!("received submission from user (ID: {user_id}) for task {task_id} of contest session {session_id}");
 
-    let verdict = grade.verdict.to_string();
-    let score = grade.score;
+    let mut transaction = app.db.pool().begin().await?;
 
+    let verdict = Verdict::Pending.to_string();
     let submission_id = sqlx::query!(
-        "INSERT INTO submissions (user_id, session_id, task, datetime, code, language, verdict, score, compile_error) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?);",
+        "INSERT INTO submissions (user_id, session_id, task, datetime, code, language, verdict, score) VALUES (?, ?, ?, ?, ?, ?, ?, 0);",
         user_id,
         session_id,
         task_id,
         now,
-        submission.code,
-        submission.language,
+        code,
+        language,
         verdict,
-        score,
-        compile_error,
     )
-    .execute(app.db.pool()).await?.last_insert_rowid();
-
-    for (idx, subtask) in grade.subtasks.iter().enumerate() {
-        let subtask_idx = idx as i64 + 1;
-        let subtask_verdict = subtask.verdict.to_string();
-        let subtask_score = subtask.score as i64;
+    .execute(&mut *transaction).await?.last_insert_rowid();
 
-        let subtask_id = sqlx::query!(
-            "INSERT INTO subtasks (submission_id, subtask, verdict, score) VALUES (?, ?, ?, ?);",
+    for (filename, contents) in outputs.into_iter().flatten() {
+        sqlx::query!(
+            "INSERT INTO outputs (submission_id, filename, contents) VALUES (?, ?, ?);",
             submission_id,
-            subtask_idx,
-            subtask_verdict,
-            subtask_score
+            filename,
+            contents
         )
-        .execute(app.db.pool())
-        .await?
-        .last_insert_rowid();
-
-        for (idx, test) in subtask.tests.iter().enumerate() {
-            let test_idx = idx as i64 + 1;
-            let test_verdict = test.verdict.to_string();
-            let test_score = test.score as i64;
-
-            let rusage = test.resource_usage;
-            let memory = rusage.map(|rusage| rusage.memory_bytes as i64);
-            let time = rusage.map(|rusage| {
-                let duration = rusage.total_time();
-                (duration.whole_milliseconds() as i64) + (duration.subsec_milliseconds() as i64)
-            });
-
-            sqlx::query!(
-                "INSERT INTO tests (subtask_id, test, memory, time, verdict, score) VALUES (?, ?, ?, ?, ?, ?);",
-                subtask_id,
-                test_idx,
-                memory,
-                time,
-                test_verdict,
-                test_score
-            )
-            .execute(app.db.pool()).await?;
-        }
+        .execute(&mut *transaction)
+        .await?;
     }
 
-    cookies.add(Cookie::new(LANGUAGE_COOKIE, submission.language));
-
-    let sessions = &mut app.sessions.write().await;
-    let session = Arc::make_mut(sessions.get_mut(&session_id).unwrap());
+    transaction.commit().await?;
 
-    session
-        .users
-        .entry((user_id, task_id))
-        .and_modify(|user_task| user_task.score = user_task.score.max(score))
-        .or_insert_with(|| UserTask {
-            score,
-            cooldown: OffsetDateTime::now_utc(),
-        });
-
-    session.update_leaderboard(user.username(), user_id)?;
+    if let Some(session) = app.sessions.write().await.get_mut(&session_id) {
+        Arc::make_mut(session)
+            .users
+            .entry((user_id, task_id))
+            .and_modify(|user_task| user_task.cooldown = now)
+            .or_insert(UserTask {
+                score: 0,
+                cooldown: now,
+            });
+    }
 
-    tracing::trace!("submission successfully judged and recorded");
+    app.queue
+        .push((session_id, user_id, task_id), submission_id);
+    tracing::trace!("submission (ID: {submission_id}) queued");
 
-    Ok(Redirect::to(&redirect_url))
+    Ok(true)
 }
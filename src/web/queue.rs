@@ -0,0 +1,539 @@
+use std::{
+    collections::HashMap,
+    sync::{self, Arc},
+    time::Duration,
+};
+
+use color_eyre::Report;
+use time::OffsetDateTime;
+use tokio::sync::{mpsc, watch, Mutex};
+
+use super::{app::App, database::Database, error::AppResult, session::UserTask};
+use crate::{
+    contest::{Contest, TaskKind},
+    judge::{
+        self, remote::JobInput, GradedTask, JudgeError, JudgeResult, Progress, Submission,
+        TestResult, Verdict,
+    },
+};
+
+/// How many times a submission is judged again after a transient failure, waiting twice as long
+/// each time, before it is given a judge error.
+const RETRIES: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Identifies the latest submission of a user to a task of a session, by session, user and task
+/// ID.
+pub type Submitter = (i64, i64, i64);
+
+/// Submissions waiting to be judged by a fixed pool of workers. Submissions are recorded as
+/// pending before they are queued, so the queue can be rebuilt from the database on startup.
+#[derive(Debug, Clone)]
+pub struct Queue {
+    tx: mpsc::UnboundedSender<(Submitter, i64)>,
+    rx: Arc<Mutex<mpsc::UnboundedReceiver<(Submitter, i64)>>>,
+    /// Kept from when a submission is queued until it is judged and no longer watched
+    progress: Arc<sync::Mutex<HashMap<Submitter, watch::Sender<Judging>>>>,
+}
+
+/// The progress of judging the latest submission of a user to a task.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Judging {
+    pub submission_id: i64,
+    pub status: Status,
+    /// Verdicts and scores of the subtasks judged so far, numbered from 1
+    pub subtasks: Vec<(usize, Verdict, u32)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Queued,
+    Compiling,
+    Running { completed: usize, total: usize },
+    Judged,
+}
+
+impl Judging {
+    fn judged(&self) -> bool {
+        self.status == Status::Judged
+    }
+
+    fn update(&mut self, progress: Progress) {
+        match progress {
+            Progress::Compiling => self.status = Status::Compiling,
+            Progress::Running { completed, total } => {
+                self.status = Status::Running { completed, total }
+            }
+            Progress::Subtask {
+                subtask,
+                verdict,
+                score,
+            } => {
+                self.subtasks.push((subtask, verdict, score));
+                self.subtasks.sort_unstable_by_key(|(subtask, ..)| *subtask);
+            }
+        }
+    }
+}
+
+impl Queue {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Queue {
+            tx,
+            rx: Arc::new(Mutex::new(rx)),
+            progress: Arc::default(),
+        }
+    }
+
+    pub fn push(&self, submitter: Submitter, submission_id: i64) {
+        let judging = Judging {
+            submission_id,
+            status: Status::Queued,
+            subtasks: Vec::new(),
+        };
+
+        {
+            let mut progress = self.progress();
+            // Watchers of judged submissions may have left since they were judged
+            progress.retain(|_, sender| !sender.borrow().judged() || sender.receiver_count() > 0);
+
+            match progress.get(&submitter) {
+                Some(sender) => {
+                    sender.send_replace(judging);
+                }
+                None => {
+                    progress.insert(submitter, watch::channel(judging).0);
+                }
+            }
+        }
+
+        // The queue holds a receiver itself, so it cannot be closed
+        let _ = self.tx.send((submitter, submission_id));
+    }
+
+    /// Watches the progress of the latest submission of a user to a task, if it is being judged
+    /// or has been judged since it was last watched.
+    pub fn subscribe(&self, submitter: Submitter) -> Option<watch::Receiver<Judging>> {
+        self.progress()
+            .get(&submitter)
+            .map(watch::Sender::subscribe)
+    }
+
+    fn progress(&self) -> sync::MutexGuard<'_, HashMap<Submitter, watch::Sender<Judging>>> {
+        self.progress
+            .lock()
+            .expect("failed to lock judging progress")
+    }
+
+    /// Updates the progress of a submission, unless a later one has been queued since. Judged
+    /// submissions are forgotten unless they are being watched.
+    fn report(&self, submitter: Submitter, submission_id: i64, update: impl FnOnce(&mut Judging)) {
+        let mut progress = self.progress();
+        let Some(sender) = progress.get(&submitter) else {
+            return;
+        };
+
+        sender.send_if_modified(|judging| {
+            let latest = judging.submission_id == submission_id;
+            if latest {
+                update(judging);
+            }
+            latest
+        });
+
+        if sender.borrow().judged() && sender.receiver_count() == 0 {
+            progress.remove(&submitter);
+        }
+    }
+
+    async fn pop(&self) -> Option<(Submitter, i64)> {
+        self.rx.lock().await.recv().await
+    }
+
+    /// Queues the submissions which were still pending when the server stopped.
+    pub async fn requeue(&self, db: &Database) -> sqlx::Result<usize> {
+        let pending = Verdict::Pending.to_string();
+        let submissions = sqlx::query!(
+            "SELECT id, session_id, user_id, task FROM submissions WHERE verdict = ? ORDER BY id;",
+            pending
+        )
+        .fetch_all(db.pool())
+        .await?;
+
+        for submission in submissions.iter() {
+            let submitter = (submission.session_id, submission.user_id, submission.task);
+            self.push(submitter, submission.id);
+        }
+
+        Ok(submissions.len())
+    }
+
+    /// Queues the submissions to a session which were given a judge error, to be judged again.
+    pub async fn retry(&self, db: &Database, session_id: i64) -> sqlx::Result<usize> {
+        let (pending, judge_error) = (
+            Verdict::Pending.to_string(),
+            Verdict::JudgeError.to_string(),
+        );
+        let mut submissions = sqlx::query!(
+            "UPDATE submissions SET verdict = ?, judge_error = NULL WHERE session_id = ? AND verdict = ?
+            RETURNING id, session_id, user_id, task;",
+            pending,
+            session_id,
+            judge_error
+        )
+        .fetch_all(db.pool())
+        .await?;
+        submissions.sort_unstable_by_key(|submission| submission.id);
+
+        for submission in submissions.iter() {
+            let submitter = (submission.session_id, submission.user_id, submission.task);
+            self.push(submitter, submission.id);
+        }
+
+        Ok(submissions.len())
+    }
+}
+
+/// Starts the judge workers, which run until the server stops. With remote workers, every
+/// submission is handed over as soon as it is queued, and waits for a free remote worker.
+pub fn spawn_workers(app: &App) {
+    if app.workers.is_some() {
+        tokio::spawn(dispatch(app.clone()));
+        tracing::debug!("judging submissions on remote workers");
+        return;
+    }
+
+    for _ in 0..app.judge_config.workers {
+        tokio::spawn(work(app.clone()));
+    }
+
+    tracing::debug!("started {} judge workers", app.judge_config.workers);
+}
+
+async fn work(app: App) {
+    while let Some((submitter, submission_id)) = app.queue.pop().await {
+        judge_logged(&app, submitter, submission_id).await;
+    }
+}
+
+async fn dispatch(app: App) {
+    while let Some((submitter, submission_id)) = app.queue.pop().await {
+        let app = app.clone();
+        tokio::spawn(async move { judge_logged(&app, submitter, submission_id).await });
+    }
+}
+
+async fn judge_logged(app: &App, submitter: Submitter, submission_id: i64) {
+    if let Err(e) = judge(app, submitter, submission_id).await {
+        tracing::error!(
+            "failed to judge submission (ID: {submission_id}): {}",
+            e.into_report()
+        );
+    }
+}
+
+/// Judges a submission and records the result. Any failure to judge it is recorded as a judge
+/// error, so that the submission does not stay pending.
+#[tracing::instrument(skip(app))]
+async fn judge(app: &App, submitter: Submitter, submission_id: i64) -> AppResult<()> {
+    let judge_error = |error: String| {
+        tracing::error!("failed to judge submission: {error}");
+        (failed(Verdict::JudgeError), None, Some(error))
+    };
+
+    let (grade, compile_error, judge_error) = match grade(app, submitter, submission_id).await {
+        Ok(Ok(grade)) => (grade, None, None),
+        Ok(Err(JudgeError::CompileError(stderr))) => {
+            (failed(Verdict::CompileError), Some(stderr), None)
+        }
+        Ok(Err(e)) => judge_error(e.to_string()),
+        Err(e) => judge_error(e.into_report().to_string()),
+    };
+
+    record(&app.db, submission_id, &grade, compile_error, judge_error).await?;
+    app.queue.report(submitter, submission_id, |judging| {
+        judging.status = Status::Judged
+    });
+
+    let (session_id, user_id, task_id) = submitter;
+    let user = sqlx::query!("SELECT username FROM users WHERE id = ?;", user_id)
+        .fetch_one(app.db.pool())
+        .await?;
+
+    if let Some(session) = app.sessions.write().await.get_mut(&session_id) {
+        let session = Arc::make_mut(session);
+        let score = grade.score;
+
+        session
+            .users
+            .entry((user_id, task_id))
+            .and_modify(|user_task| user_task.score = user_task.score.max(score))
+            .or_insert_with(|| UserTask {
+                score,
+                cooldown: OffsetDateTime::now_utc(),
+            });
+
+        session.update_leaderboard(&user.username, user_id)?;
+    }
+
+    tracing::trace!("submission successfully judged and recorded");
+
+    Ok(())
+}
+
+/// Judges a submission, retrying after transient failures.
+async fn grade(
+    app: &App,
+    submitter: Submitter,
+    submission_id: i64,
+) -> AppResult<JudgeResult<GradedTask>> {
+    let submission = sqlx::query!(
+        "SELECT submissions.task, submissions.code, submissions.language, sessions.contest_path
+        FROM submissions
+        JOIN sessions ON submissions.session_id = sessions.id
+        WHERE submissions.id = ?;",
+        submission_id
+    )
+    .fetch_one(app.db.pool())
+    .await?;
+
+    let contest = app
+        .contests
+        .iter()
+        .find(|contest| contest.path.display().to_string() == submission.contest_path)
+        .ok_or_else(|| Report::msg(format!("contest {} is not loaded", submission.contest_path)))?;
+    let task = contest
+        .tasks
+        .get(submission.task as usize - 1)
+        .cloned()
+        .ok_or_else(|| Report::msg(format!("contest has no task {}", submission.task)))?;
+
+    let input = if task.kind == TaskKind::OutputOnly {
+        let files = sqlx::query!(
+            "SELECT filename, contents FROM outputs WHERE submission_id = ? ORDER BY id;",
+            submission_id
+        )
+        .fetch_all(app.db.pool())
+        .await?;
+
+        JobInput::Outputs(
+            files
+                .into_iter()
+                .map(|file| (file.filename, file.contents))
+                .collect(),
+        )
+    } else {
+        JobInput::Code(Submission {
+            code: submission.code,
+            language: submission.language,
+        })
+    };
+
+    tracing::trace!("judging submission from user (ID: {})", submitter.1);
+
+    let queue = app.queue.clone();
+    let progress =
+        move |progress| queue.report(submitter, submission_id, |judging| judging.update(progress));
+
+    let task_idx = submission.task as usize - 1;
+    let mut retries = 0;
+    loop {
+        match run(app, contest, task_idx, input.clone(), progress.clone()).await {
+            Err(e) if e.is_transient() && retries < RETRIES => {
+                let delay = RETRY_DELAY * 2u32.pow(retries);
+                tracing::warn!("failed to judge submission, retrying in {delay:?}: {e}");
+                retries += 1;
+
+                app.queue.report(submitter, submission_id, |judging| {
+                    judging.status = Status::Queued;
+                    judging.subtasks.clear();
+                });
+                tokio::time::sleep(delay).await;
+            }
+            result => return Ok(result.map(|verdicts| judge::grade(&task, &verdicts))),
+        }
+    }
+}
+
+fn failed(verdict: Verdict) -> GradedTask {
+    GradedTask {
+        verdict,
+        score: 0,
+        subtasks: Vec::new(),
+    }
+}
+
+/// Judges a submission locally, or on a remote worker if there are any.
+async fn run(
+    app: &App,
+    contest: &Contest,
+    task_idx: usize,
+    input: JobInput,
+    progress: impl Fn(Progress) + Send + Sync + 'static,
+) -> JudgeResult<Vec<TestResult>> {
+    match &app.workers {
+        Some(workers) => workers.judge(contest, task_idx, input, progress).await,
+        None => {
+            let config = app.judge_config.clone();
+            let task = contest.tasks[task_idx].clone();
+            let rlimits = contest.rlimits;
+            tokio::task::spawn_blocking(move || input.judge(&config, &task, rlimits, &progress))
+                .await
+                .unwrap_or_else(|e| Err(JudgeError::Panicked(e.to_string())))
+        }
+    }
+}
+
+/// Records the results of a pending submission, all at once so that it is judged again from
+/// scratch if the server stops part of the way through. The outputs of an output-only
+/// submission are kept if judging failed, so that it can be retried.
+async fn record(
+    db: &Database,
+    submission_id: i64,
+    grade: &GradedTask,
+    compile_error: Option<String>,
+    judge_error: Option<String>,
+) -> sqlx::Result<()> {
+    let mut transaction = db.pool().begin().await?;
+
+    let verdict = grade.verdict.to_string();
+    let score = grade.score;
+
+    sqlx::query!(
+        "UPDATE submissions SET verdict = ?, score = ?, compile_error = ?, judge_error = ? WHERE id = ?;",
+        verdict,
+        score,
+        compile_error,
+        judge_error,
+        submission_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    for (idx, subtask) in grade.subtasks.iter().enumerate() {
+        let subtask_idx = idx as i64 + 1;
+        let subtask_verdict = subtask.verdict.to_string();
+        let subtask_score = subtask.score as i64;
+
+        let subtask_id = sqlx::query!(
+            "INSERT INTO subtasks (submission_id, subtask, verdict, score) VALUES (?, ?, ?, ?);",
+            submission_id,
+            subtask_idx,
+            subtask_verdict,
+            subtask_score
+        )
+        .execute(&mut *transaction)
+        .await?
+        .last_insert_rowid();
+
+        for test in subtask.tests.iter() {
+            let test_idx = test.test as i64 + 1;
+            let test_verdict = test.verdict.to_string();
+            let test_score = test.score;
+
+            let rusage = test.resource_usage;
+            let memory = rusage.map(|rusage| rusage.memory_bytes as i64);
+            let time = rusage.map(|rusage| rusage.total_time().whole_milliseconds() as i64);
+
+            sqlx::query!(
+                "INSERT INTO tests (subtask_id, test, memory, time, verdict, score, message, exit_code, signal) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?);",
+                subtask_id,
+                test_idx,
+                memory,
+                time,
+                test_verdict,
+                test_score,
+                test.message,
+                test.exit_code,
+                test.signal
+            )
+            .execute(&mut *transaction).await?;
+        }
+    }
+
+    if grade.verdict != Verdict::JudgeError {
+        sqlx::query!(
+            "DELETE FROM outputs WHERE submission_id = ?;",
+            submission_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    transaction.commit().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::judge::{GradedSubtask, GradedTest, ResourceUsage};
+
+    #[test]
+    fn progress_is_forgotten_once_judged_and_unwatched() {
+        let queue = Queue::new();
+        let submitter = (1, 1, 1);
+        assert!(queue.subscribe(submitter).is_none());
+
+        queue.push(submitter, 1);
+        let rx = queue.subscribe(submitter).unwrap();
+        queue.report(submitter, 1, |judging| judging.status = Status::Judged);
+        assert!(rx.borrow().judged());
+
+        // Kept for the watcher until it leaves and another submission is queued
+        assert!(queue.subscribe(submitter).is_some());
+        drop(rx);
+        queue.push((1, 1, 2), 2);
+        assert!(queue.subscribe(submitter).is_none());
+
+        queue.report((1, 1, 2), 2, |judging| judging.status = Status::Judged);
+        assert!(queue.subscribe((1, 1, 2)).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_times_in_milliseconds() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}", dir.path().join("judge.db").display());
+        let db = Database::new(&url).await.unwrap();
+
+        sqlx::query!(
+            "INSERT INTO users (id, email, username, password) VALUES (1, 'a@b.c', 'a', '');
+            INSERT INTO sessions (id, contest_name, contest_path) VALUES (1, 'c', 'c');
+            INSERT INTO submissions (id, user_id, session_id, task, datetime, code, language, verdict, score)
+            VALUES (1, 1, 1, 1, datetime('now'), '', '', 'Pending', 0);"
+        )
+        .execute(db.pool())
+        .await
+        .unwrap();
+
+        let test = GradedTest {
+            test: 0,
+            verdict: Verdict::Accepted,
+            score: 1.0,
+            message: None,
+            resource_usage: Some(ResourceUsage {
+                user_time: time::Duration::milliseconds(1200),
+                sys_time: time::Duration::milliseconds(300),
+                memory_bytes: 0,
+            }),
+            exit_code: None,
+            signal: None,
+        };
+        let grade = GradedTask {
+            verdict: Verdict::Accepted,
+            score: 100,
+            subtasks: vec![GradedSubtask {
+                verdict: Verdict::Accepted,
+                score: 100,
+                tests: vec![test],
+            }],
+        };
+        record(&db, 1, &grade, None, None).await.unwrap();
+
+        let test = sqlx::query!("SELECT time FROM tests;")
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+        assert_eq!(test.time, Some(1500));
+    }
+}
@@ -0,0 +1,531 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use tokio::{
+    io::BufReader,
+    net::{tcp::OwnedReadHalf, TcpListener, TcpStream},
+    sync::{mpsc, oneshot, Notify},
+    time::{self, Instant},
+};
+
+use crate::{
+    contest::Contest,
+    judge::{
+        remote::{self, JobInput, Outcome, TaskData, ToServer, ToWorker, Token, MAX_MESSAGE},
+        JudgeError, JudgeResult, Progress, TestResult,
+    },
+};
+
+/// How long a job waits for a worker which supports its language to connect
+const WORKER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How many workers a job is sent to before giving up, in case it is what makes them disconnect
+const MAX_ATTEMPTS: usize = 3;
+
+/// Judge workers connected over the network. Each job goes to the worker with the most free
+/// capacity out of those which support its language, and is sent to another worker if its
+/// worker disconnects before finishing it.
+#[derive(Debug, Clone)]
+pub struct Workers {
+    pool: Arc<Mutex<Pool>>,
+    /// Notified when a worker connects or finishes a job
+    available: Arc<Notify>,
+    token: Option<Token>,
+    timeout: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Pool {
+    workers: HashMap<u64, Worker>,
+    next_worker: u64,
+    next_job: u64,
+    /// The data sent to workers which ask for a task, by its hash
+    tasks: HashMap<String, Arc<TaskData>>,
+    /// Hashes of the tasks judged so far, by contest path and task index
+    hashes: HashMap<(PathBuf, usize), String>,
+}
+
+#[derive(Debug)]
+struct Worker {
+    languages: HashSet<String>,
+    capacity: usize,
+    jobs: HashMap<u64, Job>,
+    tx: mpsc::UnboundedSender<ToWorker>,
+}
+
+#[derive(Debug)]
+struct Job {
+    progress: mpsc::UnboundedSender<Progress>,
+    done: oneshot::Sender<Outcome>,
+}
+
+impl Workers {
+    /// Workers must give the token when they connect, if there is one.
+    pub fn new(token: Option<Token>) -> Self {
+        Workers {
+            pool: Arc::default(),
+            available: Arc::default(),
+            token,
+            timeout: WORKER_TIMEOUT,
+        }
+    }
+
+    fn pool(&self) -> MutexGuard<'_, Pool> {
+        self.pool.lock().expect("failed to lock worker pool")
+    }
+
+    /// Judges a submission to a task on a worker, waiting until one is free. Fails if no connected
+    /// worker supports its language for a while, or if too many workers disconnect while judging
+    /// it.
+    pub async fn judge(
+        &self,
+        contest: &Contest,
+        task: usize,
+        input: JobInput,
+        progress: impl Fn(Progress),
+    ) -> JudgeResult<Vec<TestResult>> {
+        let hash = self.task_hash(contest, task);
+        let language = match &input {
+            JobInput::Code(submission) => Some(submission.language.clone()),
+            JobInput::Outputs(_) => None,
+        };
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            let (progress_tx, mut progress_rx) = mpsc::unbounded_channel();
+            let (done_tx, mut done_rx) = oneshot::channel();
+            let job = Job {
+                progress: progress_tx,
+                done: done_tx,
+            };
+
+            self.dispatch(language.as_deref(), &hash, input.clone(), job)
+                .await?;
+
+            loop {
+                tokio::select! {
+                    Some(update) = progress_rx.recv() => progress(update),
+                    outcome = &mut done_rx => match outcome {
+                        Ok(outcome) => return outcome.into(),
+                        Err(_) => break,
+                    },
+                }
+            }
+
+            tracing::warn!("judge worker disconnected, attempt {attempt} of {MAX_ATTEMPTS}");
+        }
+
+        Err(JudgeError::Worker {
+            error: format!("{MAX_ATTEMPTS} judge workers disconnected while judging"),
+            transient: false,
+        })
+    }
+
+    async fn dispatch(
+        &self,
+        language: Option<&str>,
+        hash: &str,
+        input: JobInput,
+        job: Job,
+    ) -> JudgeResult<()> {
+        let supports =
+            |worker: &Worker| language.is_none_or(|language| worker.languages.contains(language));
+        // Set while no connected worker supports the language
+        let mut deadline = None;
+
+        loop {
+            // Registered before checking, so that a worker becoming free in between is noticed
+            let available = self.available.notified();
+
+            {
+                let mut pool = self.pool();
+                let id = pool.next_job;
+
+                let worker = pool
+                    .workers
+                    .values_mut()
+                    .filter(|worker| supports(worker) && worker.jobs.len() < worker.capacity)
+                    .max_by_key(|worker| worker.capacity - worker.jobs.len());
+
+                if let Some(worker) = worker {
+                    // A worker which has just disconnected drops the job, which is then retried
+                    let _ = worker.tx.send(ToWorker::Judge {
+                        job: id,
+                        task: hash.to_owned(),
+                        input,
+                    });
+                    worker.jobs.insert(id, job);
+                    pool.next_job += 1;
+                    return Ok(());
+                }
+
+                if pool.workers.values().any(supports) {
+                    deadline = None;
+                } else {
+                    deadline.get_or_insert_with(|| Instant::now() + self.timeout);
+                }
+            }
+
+            tracing::debug!("waiting for a free judge worker");
+            match deadline {
+                Some(deadline) => {
+                    if time::timeout_at(deadline, available).await.is_err() {
+                        return Err(JudgeError::Worker {
+                            error: match language {
+                                Some(language) => format!("no judge worker supports {language}"),
+                                None => String::from("no judge worker is connected"),
+                            },
+                            transient: false,
+                        });
+                    }
+                }
+                None => available.await,
+            }
+        }
+    }
+
+    fn task_hash(&self, contest: &Contest, task: usize) -> String {
+        let mut pool = self.pool();
+        let key = (contest.path.clone(), task);
+
+        if let Some(hash) = pool.hashes.get(&key) {
+            return hash.clone();
+        }
+
+        let data = TaskData::new(&contest.tasks[task], contest.rlimits);
+        let hash = data.hash();
+        pool.tasks.insert(hash.clone(), Arc::new(data));
+        pool.hashes.insert(key, hash.clone());
+
+        hash
+    }
+
+    /// Accepts workers until the server stops.
+    pub async fn listen(self, listener: TcpListener) -> io::Result<()> {
+        tracing::info!(
+            "listening for judge workers on {}",
+            listener.local_addr().unwrap()
+        );
+
+        loop {
+            let (stream, address) = listener.accept().await?;
+            let workers = self.clone();
+
+            tokio::spawn(async move {
+                match workers.serve(stream, address).await {
+                    Ok(()) => tracing::info!("judge worker {address} disconnected"),
+                    Err(e) => tracing::warn!("judge worker {address} disconnected: {e}"),
+                }
+            });
+        }
+    }
+
+    async fn serve(&self, stream: TcpStream, address: SocketAddr) -> io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+
+        let Some(ToServer::Hello {
+            token,
+            languages,
+            capacity,
+        }) = remote::read(&mut reader, MAX_MESSAGE).await?
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "worker did not introduce itself",
+            ));
+        };
+
+        if capacity == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "worker cannot judge anything at once",
+            ));
+        }
+
+        if let Some(expected) = &self.token {
+            if !token.is_some_and(|token| token.matches(expected)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "worker gave an invalid token",
+                ));
+            }
+        }
+
+        tracing::info!(
+            "judge worker {address} connected, judging {capacity} at once in {languages:?}"
+        );
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let sender = tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                remote::write(&mut writer, &message).await?;
+            }
+
+            io::Result::Ok(())
+        });
+
+        let id = {
+            let mut pool = self.pool();
+            let id = pool.next_worker;
+            pool.next_worker += 1;
+            pool.workers.insert(
+                id,
+                Worker {
+                    languages: languages.into_iter().collect(),
+                    capacity,
+                    jobs: HashMap::new(),
+                    tx,
+                },
+            );
+            id
+        };
+        self.available.notify_waiters();
+
+        let result = self.receive(id, &mut reader).await;
+        sender.abort();
+
+        // Dropping the unfinished jobs sends them to other workers
+        if let Some(worker) = self.pool().workers.remove(&id) {
+            if !worker.jobs.is_empty() {
+                tracing::warn!(
+                    "judge worker {address} left {} job(s) unfinished",
+                    worker.jobs.len()
+                );
+            }
+        }
+
+        result
+    }
+
+    async fn receive(&self, id: u64, reader: &mut BufReader<OwnedReadHalf>) -> io::Result<()> {
+        while let Some(message) = remote::read(reader, MAX_MESSAGE).await? {
+            let mut pool = self.pool();
+            let pool = &mut *pool;
+            let Some(worker) = pool.workers.get_mut(&id) else {
+                break;
+            };
+
+            match message {
+                ToServer::Hello { .. } => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "worker introduced itself twice",
+                    ))
+                }
+                ToServer::Fetch { hash } => match pool.tasks.get(&hash) {
+                    Some(data) => {
+                        let data = Box::new(data.as_ref().clone());
+                        let _ = worker.tx.send(ToWorker::Task { hash, data });
+                    }
+                    None => tracing::warn!("judge worker asked for unknown task {hash}"),
+                },
+                ToServer::Progress { job, progress } => {
+                    if let Some(job) = worker.jobs.get(&job) {
+                        let _ = job.progress.send(progress);
+                    }
+                }
+                ToServer::Done { job, outcome } => {
+                    if let Some(job) = worker.jobs.remove(&job) {
+                        let _ = job.done.send(outcome);
+                    }
+
+                    self.available.notify_waiters();
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+
+    use tokio::{net::tcp::OwnedWriteHalf, task::JoinHandle};
+
+    use super::*;
+    use crate::{
+        contest::ContestResourceLimits,
+        judge::{test_task, Submission},
+    };
+
+    /// A worker on the other end of a connection, which answers jobs by hand
+    struct FakeWorker {
+        reader: BufReader<OwnedReadHalf>,
+        writer: OwnedWriteHalf,
+    }
+
+    impl FakeWorker {
+        async fn connect(workers: &Workers, address: SocketAddr, capacity: usize) -> Self {
+            let connected = workers.pool().workers.len() + 1;
+            let (reader, mut writer) = TcpStream::connect(address).await.unwrap().into_split();
+            let hello = ToServer::Hello {
+                token: None,
+                languages: vec![String::from("python")],
+                capacity,
+            };
+            remote::write(&mut writer, &hello).await.unwrap();
+
+            while workers.pool().workers.len() < connected {
+                tokio::task::yield_now().await;
+            }
+
+            FakeWorker {
+                reader: BufReader::new(reader),
+                writer,
+            }
+        }
+
+        async fn job(&mut self) -> u64 {
+            let message = soon(remote::read(&mut self.reader, u64::MAX)).await;
+            match message.unwrap() {
+                Some(ToWorker::Judge { job, .. }) => job,
+                message => panic!("expected a job, got {message:?}"),
+            }
+        }
+
+        async fn finish(&mut self, job: u64) {
+            let done = ToServer::Done {
+                job,
+                outcome: Outcome::Judged(Vec::new()),
+            };
+            remote::write(&mut self.writer, &done).await.unwrap();
+        }
+    }
+
+    async fn soon<T>(future: impl Future<Output = T>) -> T {
+        time::timeout(Duration::from_secs(5), future)
+            .await
+            .expect("timed out")
+    }
+
+    fn contest() -> Arc<Contest> {
+        Arc::new(Contest {
+            name: String::from("contest"),
+            path: PathBuf::from("contest"),
+            page: String::new(),
+            tasks: vec![test_task(&[&[0]], 1)],
+            duration: ::time::Duration::ZERO,
+            cooldown: ::time::Duration::ZERO,
+            leaderboard_size: 0,
+            rlimits: toml::from_str::<ContestResourceLimits>(
+                "build = { time = 1, memory = \"256MiB\" }\nrun = { time = 1, memory = \"256MiB\" }",
+            )
+            .unwrap(),
+        })
+    }
+
+    fn judge(
+        workers: &Workers,
+        contest: &Arc<Contest>,
+        language: &str,
+    ) -> JoinHandle<JudgeResult<Vec<TestResult>>> {
+        let (workers, contest) = (workers.clone(), contest.clone());
+        let submission = Submission {
+            code: String::new(),
+            language: language.to_owned(),
+        };
+
+        tokio::spawn(async move {
+            workers
+                .judge(&contest, 0, JobInput::Code(submission), |_| {})
+                .await
+        })
+    }
+
+    async fn listen() -> (Workers, SocketAddr) {
+        let workers = Workers {
+            timeout: Duration::from_millis(100),
+            ..Workers::new(None)
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(workers.clone().listen(listener));
+
+        (workers, address)
+    }
+
+    #[tokio::test]
+    async fn balances_and_requeues_jobs() {
+        let (workers, address) = listen().await;
+        let mut big = FakeWorker::connect(&workers, address, 3).await;
+        let mut small = FakeWorker::connect(&workers, address, 1).await;
+        let contest = contest();
+
+        // Both jobs go to the worker with more free capacity
+        let first = judge(&workers, &contest, "python");
+        big.job().await;
+        let second = judge(&workers, &contest, "python");
+        big.job().await;
+
+        // Then to the other worker one at a time once that one disconnects
+        drop(big);
+        let job = small.job().await;
+        small.finish(job).await;
+        let job = small.job().await;
+        small.finish(job).await;
+
+        assert!(soon(first).await.unwrap().is_ok());
+        assert!(soon(second).await.unwrap().is_ok());
+
+        let unsupported = soon(judge(&workers, &contest, "c")).await.unwrap();
+        assert!(matches!(
+            unsupported,
+            Err(JudgeError::Worker {
+                transient: false,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_lost_jobs() {
+        let (workers, address) = listen().await;
+        let contest = contest();
+        let judging = judge(&workers, &contest, "python");
+
+        for _ in 0..MAX_ATTEMPTS {
+            let mut worker = FakeWorker::connect(&workers, address, 1).await;
+            worker.job().await;
+            drop(worker);
+
+            soon(async {
+                while !workers.pool().workers.is_empty() {
+                    tokio::task::yield_now().await;
+                }
+            })
+            .await;
+        }
+
+        assert!(matches!(
+            soon(judging).await.unwrap(),
+            Err(JudgeError::Worker {
+                transient: false,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_workers_without_capacity() {
+        let (_workers, address) = listen().await;
+        let (reader, mut writer) = TcpStream::connect(address).await.unwrap().into_split();
+        let hello = ToServer::Hello {
+            token: None,
+            languages: vec![String::from("python")],
+            capacity: 0,
+        };
+        remote::write(&mut writer, &hello).await.unwrap();
+
+        let mut reader = BufReader::new(reader);
+        let closed = soon(remote::read::<ToWorker>(&mut reader, u64::MAX)).await;
+        assert!(matches!(closed, Ok(None)));
+    }
+}
@@ -0,0 +1,172 @@
+use std::{collections::HashMap, io, path::PathBuf, sync::Arc, time::Duration};
+
+use thiserror::Error;
+use tokio::{
+    fs,
+    io::BufReader,
+    net::TcpStream,
+    sync::mpsc::{self, UnboundedSender},
+};
+
+use crate::{
+    contest::{ContestResourceLimits, Task},
+    judge::{
+        remote::{self, JobInput, Outcome, ToServer, ToWorker, Token},
+        Config as JudgeConfig, JudgeError,
+    },
+};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct Config {
+    pub server_address: String,
+    pub judge_config_path: PathBuf,
+    /// The shared secret the web server expects from workers
+    pub token: Option<Token>,
+}
+
+#[derive(Debug, Error)]
+pub enum WorkerError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to parse judge config: {0}")]
+    Config(#[from] toml::de::Error),
+    #[error(transparent)]
+    Judge(#[from] JudgeError),
+}
+
+/// Tasks compiled from the data sent by the web server, by its hash. Tasks are kept across
+/// reconnections, so they are only sent again if they change.
+type TaskCache = HashMap<String, Arc<(Task, ContestResourceLimits)>>;
+
+/// Judges submissions for a web server, reconnecting whenever the connection is lost.
+#[tracing::instrument]
+pub async fn run(config: Config) -> Result<(), WorkerError> {
+    let judge_config: Arc<JudgeConfig> = {
+        let judge_config_file = fs::read_to_string(&config.judge_config_path).await?;
+        let mut judge_config: JudgeConfig = toml::from_str(&judge_config_file)?;
+        judge_config.check_users()?;
+        judge_config.check_cgroup();
+        judge_config.check_namespaces();
+        Arc::new(judge_config)
+    };
+
+    let mut cache = TaskCache::new();
+
+    loop {
+        match serve(&config, &judge_config, &mut cache).await {
+            Ok(()) => tracing::warn!("web server closed the connection"),
+            Err(e) => tracing::warn!("lost connection to web server: {e}"),
+        }
+
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn serve(
+    config: &Config,
+    judge_config: &Arc<JudgeConfig>,
+    cache: &mut TaskCache,
+) -> io::Result<()> {
+    let stream = TcpStream::connect(&config.server_address).await?;
+    tracing::info!("connected to web server at {}", config.server_address);
+
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let sender = tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            remote::write(&mut writer, &message).await?;
+        }
+
+        io::Result::Ok(())
+    });
+
+    let _ = tx.send(ToServer::Hello {
+        token: config.token.clone(),
+        languages: judge_config
+            .languages
+            .iter()
+            .map(|language| language.name.clone())
+            .collect(),
+        capacity: judge_config.workers,
+    });
+
+    // Jobs waiting for the data of their task, by its hash
+    let mut fetching: HashMap<String, Vec<(u64, JobInput)>> = HashMap::new();
+
+    // The server is trusted, and the data of a task can be large
+    while let Some(message) = remote::read(&mut reader, u64::MAX).await? {
+        match message {
+            ToWorker::Judge { job, task, input } => match cache.get(&task) {
+                Some(task) => spawn_job(judge_config.clone(), task.clone(), job, input, &tx),
+                None => {
+                    let jobs = fetching.entry(task.clone()).or_default();
+                    if jobs.is_empty() {
+                        tracing::debug!("fetching task {task}");
+                        let _ = tx.send(ToServer::Fetch { hash: task });
+                    }
+
+                    jobs.push((job, input));
+                }
+            },
+            ToWorker::Task { hash, data } => {
+                let jobs = fetching.remove(&hash).unwrap_or_default();
+
+                let config = judge_config.clone();
+                let compiled = tokio::task::spawn_blocking(move || data.compile(&config)).await?;
+
+                match compiled {
+                    Ok(task) => {
+                        let task = Arc::new(task);
+                        cache.insert(hash, task.clone());
+
+                        for (job, input) in jobs {
+                            spawn_job(judge_config.clone(), task.clone(), job, input, &tx);
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!("failed to compile task {hash}: {e}");
+
+                        for (job, _) in jobs {
+                            let outcome = Outcome::Failed {
+                                error: format!("failed to compile task: {e}"),
+                                transient: e.is_transient(),
+                            };
+                            let _ = tx.send(ToServer::Done { job, outcome });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    sender.abort();
+    Ok(())
+}
+
+/// Judges a submission on a blocking thread, sending its progress and outcome to the server. A
+/// job outlives a lost connection, but the server will have judged it elsewhere by then.
+fn spawn_job(
+    config: Arc<JudgeConfig>,
+    task: Arc<(Task, ContestResourceLimits)>,
+    job: u64,
+    input: JobInput,
+    tx: &UnboundedSender<ToServer>,
+) {
+    let tx = tx.clone();
+
+    tokio::task::spawn_blocking(move || {
+        let (task, rlimits) = task.as_ref();
+        tracing::debug!("judging job {job} for task {}", task.name);
+
+        let progress = |progress| {
+            let _ = tx.send(ToServer::Progress { job, progress });
+        };
+        let outcome = input.judge(&config, task, *rlimits, &progress).into();
+
+        let _ = tx.send(ToServer::Done { job, outcome });
+    });
+}